@@ -1,161 +1,412 @@
 use ast;
 use instr;
 use span::{Span, Spanned};
-use functions::find_function;
+use colour;
+use functions::{candidates, find_function, find_operator, find_unary_operator, find_user_function, is_builtin, UserFunction};
 
+use diagnostic::Diagnostic;
+
+use std::fmt;
+use std::mem;
+use std::error::Error;
 use std::collections::{HashMap, BTreeSet};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum AnalyseError {
-    IncorrectReturnType(Span),
+    IncorrectReturnType(Span, instr::Type, instr::Type),
     IncorrectTupleTypes(Span),
-    IncorrectBinOpTypes(Span),
-    IncorrectAssignmentType(Span),
-    UndefinedName(Span),
+    IncorrectBinOpTypes(Span, instr::Type, instr::Type),
+    IncorrectUnaryOpType(Span, instr::Type),
+    IncorrectAssignmentType(Span, instr::Type, instr::Type),
+    UndefinedName(Span, String),
+    DuplicateName(Span, String),
     ExpectedReturn(Span),
-    ExpectedBoolean(Span),
+    ExpectedBoolean(Span, instr::Type),
     ExpectedVoidExprStmt(Span),
-    InvalidApplication(Span),
+    ExpectedValue(Span),
+    ExpectedElse(Span),
+    IncorrectBranchTypes(Span, instr::Type, instr::Type),
+    InvalidApplication(Span, String, Vec<instr::Type>, Vec<String>),
+    InvalidImageType(Span, instr::Type),
+    InvalidSwizzle(Span, instr::Type, String),
+    InvalidLiteral(Span, String),
+    LiteralOutOfRange(Span, String),
+    NeverProducesValue(Span),
+    ReservedName(Span, String),
+}
+
+impl AnalyseError {
+    pub fn span(&self) -> Span {
+        match *self {
+            AnalyseError::IncorrectReturnType(span, _, _) |
+            AnalyseError::IncorrectTupleTypes(span) |
+            AnalyseError::IncorrectBinOpTypes(span, _, _) |
+            AnalyseError::IncorrectUnaryOpType(span, _) |
+            AnalyseError::IncorrectAssignmentType(span, _, _) |
+            AnalyseError::UndefinedName(span, _) |
+            AnalyseError::DuplicateName(span, _) |
+            AnalyseError::ExpectedReturn(span) |
+            AnalyseError::ExpectedBoolean(span, _) |
+            AnalyseError::ExpectedVoidExprStmt(span) |
+            AnalyseError::ExpectedValue(span) |
+            AnalyseError::ExpectedElse(span) |
+            AnalyseError::IncorrectBranchTypes(span, _, _) |
+            AnalyseError::InvalidApplication(span, _, _, _) |
+            AnalyseError::InvalidImageType(span, _) |
+            AnalyseError::InvalidSwizzle(span, _, _) |
+            AnalyseError::InvalidLiteral(span, _) |
+            AnalyseError::LiteralOutOfRange(span, _) |
+            AnalyseError::NeverProducesValue(span) |
+            AnalyseError::ReservedName(span, _) => span
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.span(), self.to_string())
+    }
 }
 
-struct Env {
-    names: HashMap<String, instr::Type>,
+impl fmt::Display for AnalyseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            AnalyseError::IncorrectReturnType(_, expected, found) => write!(f, "expected {} return value, found {}", expected, found),
+            AnalyseError::IncorrectTupleTypes(_) => write!(f, "tuple elements must all be floats"),
+            AnalyseError::IncorrectBinOpTypes(_, lhs, rhs) => write!(f, "invalid operand types for binary operator: {} and {}", lhs, rhs),
+            AnalyseError::IncorrectUnaryOpType(_, ty) => write!(f, "invalid operand type for unary operator: {}", ty),
+            AnalyseError::IncorrectAssignmentType(_, expected, found) => write!(f, "cannot assign {} to a variable of type {}", found, expected),
+            AnalyseError::UndefinedName(_, ref name) => write!(f, "undefined name `{}`", name),
+            AnalyseError::DuplicateName(_, ref name) => write!(f, "`{}` is already defined", name),
+            AnalyseError::ExpectedReturn(_) => write!(f, "not every path returns a value"),
+            AnalyseError::ExpectedBoolean(_, found) => write!(f, "expected bool in if condition, found {}", found),
+            AnalyseError::ExpectedVoidExprStmt(_) => write!(f, "unexpected value at the end of a block that cannot return one"),
+            AnalyseError::ExpectedValue(_) => write!(f, "expected a value at the end of the block"),
+            AnalyseError::ExpectedElse(_) => write!(f, "if expression is missing an else branch"),
+            AnalyseError::IncorrectBranchTypes(_, t, e) => write!(f, "if and else branches have different types: {} and {}", t, e),
+            AnalyseError::InvalidApplication(_, ref name, ref args, ref candidates) => {
+                let args = args.iter().map(|ty| ty.to_string()).collect::<Vec<_>>();
+                try!(write!(f, "no function `{}` taking ({})", name, args.join(", ")));
+
+                if candidates.is_empty() {
+                    Ok(())
+                } else {
+                    write!(f, "; candidates are `{}`", candidates.join("`, `"))
+                }
+            },
+            AnalyseError::InvalidImageType(_, found) => write!(f, "image must return vec3 or vec4, found {}", found),
+            AnalyseError::InvalidSwizzle(_, ty, ref field) => write!(f, "no field `{}` on {}", field, ty),
+            AnalyseError::InvalidLiteral(_, ref lit) => write!(f, "malformed number `{}`", lit),
+            AnalyseError::LiteralOutOfRange(_, ref lit) => write!(f, "number `{}` is out of range for a float", lit),
+            AnalyseError::NeverProducesValue(_) => write!(f, "if expression never produces a value"),
+            AnalyseError::ReservedName(_, ref name) => write!(f, "`{}` is reserved by GLSL", name),
+        }
+    }
+}
+
+impl Error for AnalyseError {}
+
+struct Env<'a> {
+    /// The required return type, or `None` for an image until its first return decides it
+    ret: Option<instr::Type>,
+    scopes: Vec<HashMap<String, instr::Type>>,
     used: BTreeSet<ast::KeyVar>,
+    builtins: BTreeSet<String>,
+    functions: &'a [UserFunction],
+    errors: Vec<AnalyseError>,
+    hoisted: Vec<(instr::Instr, Span)>,
+    temps: usize,
 }
 
-impl Env {
-    fn new() -> Env {
+impl<'a> Env<'a> {
+    fn new(ret: Option<instr::Type>, functions: &'a [UserFunction]) -> Env<'a> {
         Env {
-            names: HashMap::new(),
-            used: BTreeSet::new()
+            ret: ret,
+            scopes: vec![HashMap::new()],
+            used: BTreeSet::new(),
+            builtins: BTreeSet::new(),
+            functions: functions,
+            errors: Vec::new(),
+            hoisted: Vec::new(),
+            temps: 0
         }
     }
 
     fn lookup(&self, name: &str) -> Option<instr::Type> {
-        self.names.get(name).cloned()
+        self.scopes.iter().rev().filter_map(|scope| scope.get(name)).next().cloned()
     }
 
     fn insert<S: Into<String>>(&mut self, name: S, ty: instr::Type) {
-        self.names.insert(name.into(), ty);
+        self.scopes.last_mut().unwrap().insert(name.into(), ty);
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
     }
 
     fn use_var(&mut self, var: ast::KeyVar) {
         self.used.insert(var);
     }
+
+    fn use_builtin(&mut self, name: &str) {
+        self.builtins.insert(name.to_owned());
+    }
+
+    fn error(&mut self, err: AnalyseError) {
+        self.errors.push(err);
+    }
+
+    /// A fresh variable name that cannot clash with any name in the script
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("_tmp{}", self.temps)
+    }
+
+    /// Queues instructions that must run before the statement currently being analysed
+    fn hoist(&mut self, instr: instr::Instr, span: Span) {
+        self.hoisted.push((instr, span));
+    }
 }
 
 impl ast::AST {
-    pub fn analyse(&self) -> Result<::Shady, AnalyseError> {
+    pub fn analyse(&self) -> Result<::Shady, Vec<AnalyseError>> {
         let mut shady = ::Shady::new();
+        let mut functions: Vec<UserFunction> = Vec::new();
+        let mut errors = Vec::new();
 
         for item in &self.0 {
-            shady.push_item(try!(analyse_item(&item)));
+            let analysed = analyse_item(&functions, &mut errors, &item);
+
+            if let ast::ItemKind::Function(ref sig) = item.data.item {
+                if functions.iter().any(|f| f.name == sig.name) {
+                    errors.push(AnalyseError::DuplicateName(item.span, sig.name.clone()));
+                } else {
+                    functions.push(UserFunction {
+                        name: sig.name.clone(),
+                        args: sig.params.iter().map(|param| analyse_type(param.data.ty)).collect(),
+                        ret: analysed.ret,
+                        vars: analysed.vars.clone()
+                    });
+                }
+            }
+
+            shady.push_item(analysed);
+        }
+
+        if errors.is_empty() {
+            Ok(shady)
+        } else {
+            Err(errors)
         }
+    }
+}
 
-        Ok(shady)
+fn analyse_type(ty: ast::Type) -> instr::Type {
+    match ty {
+        ast::Type::Float => instr::Type::Float,
+        ast::Type::Bool => instr::Type::Bool,
+        ast::Type::Vec2 => instr::Type::Vec2,
+        ast::Type::Vec3 => instr::Type::Vec3,
+        ast::Type::Vec4 => instr::Type::Vec4,
     }
 }
 
-fn analyse_item(item: &Spanned<ast::Item>) -> Result<instr::Item, AnalyseError> {
-    let mut env = Env::new();
+/// GLSL keywords, and the words it reserves for future use
+const GLSL_KEYWORDS: &'static [&'static str] = &[
+    "active", "asm", "attribute", "break", "buffer", "case", "cast", "centroid", "class", "coherent",
+    "common", "const", "continue", "default", "discard", "do", "double", "else", "enum", "extern",
+    "external", "false", "filter", "fixed", "flat", "for", "goto", "half", "highp", "if", "in",
+    "inline", "inout", "input", "int", "interface", "invariant", "layout", "long", "lowp", "main",
+    "mediump", "namespace", "noinline", "noperspective", "out", "output", "packed", "partition",
+    "patch", "precise", "precision", "public", "readonly", "resource", "restrict", "return",
+    "sample", "sampler1D", "sampler2D", "sampler3D", "samplerCube", "shared", "short", "sizeof",
+    "smooth", "static", "struct", "subroutine", "superp", "switch", "template", "this", "true",
+    "typedef", "uint", "uniform", "union", "unsigned", "using", "varying", "void", "volatile",
+    "while", "writeonly",
+];
+
+/// Whether GLSL would reject a declaration of `name`, either as a keyword or
+/// because it uses the `gl_` prefix or a double underscore
+fn reserved(name: &str) -> bool {
+    name.starts_with("gl_") || name.contains("__") || GLSL_KEYWORDS.contains(&name) ||
+        ["mat", "dmat", "ivec", "uvec", "bvec", "dvec"].iter()
+            .any(|prefix| name.starts_with(prefix) && name[prefix.len()..].starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// The name a variable is emitted under. Variables may shadow key variables,
+/// which are parameters of the same GLSL function, and builtins or user
+/// functions, which GLSL shares a namespace with, so those get renamed. Script
+/// names can't begin with an underscore, so the renamed form is always free.
+fn local_name(name: &str) -> String {
+    if ast::KeyVar::from_name(name).is_some() || is_builtin(name) || name.starts_with("fn_") {
+        format!("_{}", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+fn analyse_item(functions: &[UserFunction], errors: &mut Vec<AnalyseError>, item: &Spanned<ast::Item>) -> instr::Item {
+    let ret = match item.data.item {
+        ast::ItemKind::Image => None,
+        ast::ItemKind::Function(ref sig) => Some(analyse_type(sig.ret)),
+    };
+
+    let mut env = Env::new(ret, functions);
+    let mut params = Vec::new();
+
+    if let ast::ItemKind::Function(ref sig) = item.data.item {
+        // Functions are emitted with a `fn_` prefix, so only that form matters
+        if reserved(&format!("fn_{}", sig.name)) {
+            env.error(AnalyseError::ReservedName(item.span, sig.name.clone()));
+        }
+
+        for param in &sig.params {
+            let ty = analyse_type(param.data.ty);
 
-    let block = try!(analyse_block(&mut env, &item.data.block, Some(&mut |block, env, expr| {
-        let e = try!(analyse_expr(env, expr));
+            if reserved(&param.data.name) {
+                env.error(AnalyseError::ReservedName(param.span, param.data.name.clone()));
+            }
 
-        if let Some(ty) = block.ret {
-            if ty != e.ty {
-                return Err(AnalyseError::IncorrectReturnType(expr.span));
+            if env.lookup(&param.data.name).is_some() {
+                env.error(AnalyseError::DuplicateName(param.span, param.data.name.clone()));
+            } else {
+                env.insert(param.data.name.clone(), ty);
             }
-        };
 
-        println!("{:?}", block);
-        println!("{:?}", e);
+            params.push((local_name(&param.data.name), ty));
+        }
+    }
 
-        block.ret = Some(e.ty);
+    let block = analyse_block(&mut env, &item.data.block, Some(&mut |block, env, expr| {
+        let e = analyse_expr(env, expr);
+        flush_hoisted(env, &mut block.instrs, &mut block.ret);
+        merge_ret(env, &mut block.ret, e.ty, expr.span);
         block.instrs.push(instr::Instr::Return(e));
-        Ok(())
-    })));
-
-    match item.data.item {
-        ast::ItemKind::Image => if let Some(instr::Type::Vec3) = block.ret {
-            Ok(instr::Item {
-                ret: instr::Type::Vec3,
-                kind: ast::ItemKind::Image,
-                instrs: block.instrs,
-                vars: env.used
-            })
-        } else {
-            unimplemented!();
+    }));
+
+    if !returns(&block.instrs) {
+        let end = item.data.block.span.end;
+        env.error(AnalyseError::ExpectedReturn(Span { begin: end - 1, end: end }));
+    }
+
+    errors.extend(env.errors);
+
+    instr::Item {
+        ret: env.ret.unwrap_or(instr::Type::Vec3),
+        kind: item.data.item.clone(),
+        params: params,
+        instrs: block.instrs,
+        vars: env.used,
+        builtins: env.builtins
+    }
+}
+
+fn merge_ret(env: &mut Env, ret: &mut Option<instr::Type>, ty: instr::Type, span: Span) {
+    match env.ret {
+        _ if ty == instr::Type::Error => (),
+
+        Some(expected) => if ty != expected {
+            env.error(AnalyseError::IncorrectReturnType(span, expected, ty))
+        },
+
+        None => match ty {
+            instr::Type::Vec3 | instr::Type::Vec4 => env.ret = Some(ty),
+            _ => env.error(AnalyseError::InvalidImageType(span, ty))
         },
     }
+
+    *ret = Some(env.ret.unwrap_or(ty));
 }
 
-fn analyse_block(env: &mut Env, block: &Spanned<ast::Block>, expr_handler: Option<&mut FnMut(&mut instr::Block, &mut Env, &Spanned<ast::Expr>) -> Result<(), AnalyseError>>) -> Result<instr::Block, AnalyseError> {
+/// Whether every path through the instructions ends in a return
+fn returns(instrs: &[instr::Instr]) -> bool {
+    instrs.iter().any(|inst| match *inst {
+        instr::Instr::Return(_) => true,
+        instr::Instr::ITE(_, ref t, Some(ref e)) => returns(&t.instrs) && returns(&e.instrs),
+        _ => false
+    })
+}
+
+/// Moves any instructions hoisted out of the last analysed expression into
+/// `instrs`, merging the return types of value-producing ifs into `ret`
+fn flush_hoisted(env: &mut Env, instrs: &mut Vec<instr::Instr>, ret: &mut Option<instr::Type>) {
+    for (inst, span) in mem::replace(&mut env.hoisted, Vec::new()) {
+        if let instr::Instr::ITE(_, ref t, Some(ref e)) = inst {
+            for ty in t.ret.iter().chain(e.ret.iter()) {
+                merge_ret(env, ret, *ty, span);
+            }
+        }
+
+        instrs.push(inst);
+    }
+}
+
+/// Handles the value a block ends with, which images, functions and the
+/// branches of an if expression each use differently
+type ExprHandler<'h> = &'h mut FnMut(&mut instr::Block, &mut Env, &Spanned<ast::Expr>);
+
+fn analyse_block(env: &mut Env, block: &Spanned<ast::Block>, expr_handler: Option<ExprHandler>) -> instr::Block {
     let mut stmts = Vec::new();
     let mut ret = None;
 
     for stmt in &block.data.stmts {
         match stmt.data {
             ast::Stmt::Assignment(ref name, ref expr) => {
-                let expr = try!(analyse_expr(env, expr));
+                let expr = analyse_expr(env, expr);
+                flush_hoisted(env, &mut stmts, &mut ret);
 
                 match env.lookup(name) {
-                    Some(ty) => if expr.ty != ty {
-                        return Err(AnalyseError::IncorrectAssignmentType(stmt.span))
-                    } else {
-                        stmts.push(instr::Instr::Assignment(name.clone(), expr));
+                    Some(ty) => {
+                        if expr.ty != ty && expr.ty != instr::Type::Error && ty != instr::Type::Error {
+                            env.error(AnalyseError::IncorrectAssignmentType(stmt.span, ty, expr.ty))
+                        }
+
+                        stmts.push(instr::Instr::Assignment(local_name(name), expr));
                     },
 
                     None => {
+                        if reserved(name) {
+                            env.error(AnalyseError::ReservedName(stmt.span, name.clone()));
+                        }
+
                         env.insert(name.clone(), expr.ty);
-                        stmts.push(instr::Instr::Decl(name.clone(), expr.ty, Some(expr.expr)))
+                        stmts.push(instr::Instr::Decl(local_name(name), expr.ty, Some(expr.expr)))
                     }
                 }
             },
 
             ast::Stmt::Return(ref expr) => {
-                let expr = try!(analyse_expr(env, expr));
-
-                if let Some(ty) = ret {
-                    if expr.ty != ty {
-                        return Err(AnalyseError::IncorrectReturnType(stmt.span));
-                    }
-                } else {
-                    ret = Some(expr.ty)
-                };
-
+                let expr = analyse_expr(env, expr);
+                flush_hoisted(env, &mut stmts, &mut ret);
+                merge_ret(env, &mut ret, expr.ty, stmt.span);
                 stmts.push(instr::Instr::Return(expr));
             },
 
             ast::Stmt::Expr(ast::ExprStmt::ITE(ref exprs)) => {
-                let i = try!(analyse_expr(env, &exprs.0));
-                if i.ty != instr::Type::Bool {
-                    return Err(AnalyseError::ExpectedBoolean(exprs.0.span));
+                let i = analyse_expr(env, &exprs.0);
+                if i.ty != instr::Type::Bool && i.ty != instr::Type::Error {
+                    env.error(AnalyseError::ExpectedBoolean(exprs.0.span, i.ty));
                 }
 
-                // TODO: Parent environment!!!
-                let t = try!(analyse_block(env, &exprs.1, None));
+                flush_hoisted(env, &mut stmts, &mut ret);
+
+                env.push_scope();
+                let t = analyse_block(env, &exprs.1, None);
+                env.pop_scope();
+
                 if let Some(ety) = t.ret {
-                    if let Some(ty) = ret {
-                        if ty != ety {
-                            return Err(AnalyseError::IncorrectReturnType(exprs.1.span));
-                        }
-                    } else {
-                        ret = t.ret
-                    }
+                    merge_ret(env, &mut ret, ety, exprs.1.span);
                 }
 
                 let e = if let Some(ref b) = exprs.2 {
-                    // TODO: Parent environment!!!
-                    let e = try!(analyse_block(env, &b, None));
+                    env.push_scope();
+                    let e = analyse_block(env, &b, None);
+                    env.pop_scope();
+
                     if let Some(ety) = e.ret {
-                        if let Some(ty) = ret {
-                            if ty != ety {
-                                return Err(AnalyseError::IncorrectReturnType(b.span));
-                            }
-                        } else {
-                            ret = e.ret
-                        }
+                        merge_ret(env, &mut ret, ety, b.span);
                     }
 
                     Some(e)
@@ -175,241 +426,354 @@ fn analyse_block(env: &mut Env, block: &Spanned<ast::Block>, expr_handler: Optio
 
     if let Some(ref expr) = block.data.expr {
         if let Some(handler) = expr_handler {
-            try!(handler(&mut b, env, expr))
+            handler(&mut b, env, expr)
         } else {
-            return Err(AnalyseError::ExpectedVoidExprStmt(expr.span))
+            env.error(AnalyseError::ExpectedVoidExprStmt(expr.span))
         }
     };
 
-    Ok(b)
+    b
 }
 
-fn analyse_expr(env: &mut Env, expr: &Spanned<ast::Expr>) -> Result<instr::Expr, AnalyseError> {
+fn analyse_expr(env: &mut Env, expr: &Spanned<ast::Expr>) -> instr::Expr {
     match expr.data {
-        ast::Expr::KeyVar(var) => {
-            env.use_var(var);
+        ast::Expr::Literal(ref lit) => match lit.parse::<f64>() {
+            // Shaders use 32 bit floats, so anything that overflows or
+            // flushes to zero there would silently change the script
+            Ok(value) if !(value as f32).is_finite() || (value != 0.0 && value as f32 == 0.0) => {
+                env.error(AnalyseError::LiteralOutOfRange(expr.span, lit.clone()));
+
+                instr::Expr {
+                    ty: instr::Type::Error,
+                    expr: instr::ExprKind::Literal(0.0)
+                }
+            },
 
-            Ok(instr::Expr {
+            Ok(value) => instr::Expr {
                 ty: instr::Type::Float,
-                expr: instr::ExprKind::KeyVar(var)
-            })
+                expr: instr::ExprKind::Literal(value)
+            },
+
+            Err(_) => {
+                env.error(AnalyseError::InvalidLiteral(expr.span, lit.clone()));
+
+                instr::Expr {
+                    ty: instr::Type::Error,
+                    expr: instr::ExprKind::Literal(0.0)
+                }
+            }
         },
 
-        ast::Expr::Literal(ref lit) => Ok(instr::Expr {
-            ty: instr::Type::Float,
-            expr: instr::ExprKind::Literal(lit.clone())
-        }),
+        ast::Expr::Colour(ref channels) => instr::Expr {
+            ty: if channels.len() == 4 { instr::Type::Vec4 } else { instr::Type::Vec3 },
+            expr: instr::ExprKind::Colour(channels.clone())
+        },
 
-        ast::Expr::Bool(b) => Ok(instr::Expr {
+        ast::Expr::Bool(b) => instr::Expr {
             ty: instr::Type::Bool,
             expr: instr::ExprKind::Bool(b)
-        }),
+        },
 
-        ast::Expr::Var(ref name) => env.lookup(name)
-            .ok_or(AnalyseError::UndefinedName(expr.span))
-            .map(|ty| instr::Expr {
+        ast::Expr::Var(ref name) => match (env.lookup(name), ast::KeyVar::from_name(name), colour::named(name)) {
+            (Some(ty), _, _) => instr::Expr {
                 ty: ty,
-                expr: instr::ExprKind::Var(name.clone())
-            }),
+                expr: instr::ExprKind::Var(local_name(name))
+            },
+
+            (None, Some(var), _) => {
+                env.use_var(var);
+
+                instr::Expr {
+                    ty: instr::Type::Float,
+                    expr: instr::ExprKind::KeyVar(var)
+                }
+            },
+
+            (None, None, Some(rgb)) => instr::Expr {
+                ty: instr::Type::Vec3,
+                expr: instr::ExprKind::Colour(rgb.to_vec())
+            },
+
+            (None, None, None) => {
+                // Poison the name so that later uses don't report it again
+                env.error(AnalyseError::UndefinedName(expr.span, name.clone()));
+                env.insert(name.clone(), instr::Type::Error);
+
+                instr::Expr {
+                    ty: instr::Type::Error,
+                    expr: instr::ExprKind::Var(name.clone())
+                }
+            }
+        },
 
         ast::Expr::App(ref name, ref exprs) => {
             let mut tys = Vec::new();
             let mut es = Vec::new();
 
             for expr in exprs {
-                let e = try!(analyse_expr(env, &expr));
+                let e = analyse_expr(env, &expr);
                 tys.push(e.ty);
                 es.push(e.expr);
             }
 
-            if let Some(ty) = find_function(name, &tys) {
-                Ok(instr::Expr {
+            let functions = env.functions;
+
+            if tys.contains(&instr::Type::Error) {
+                instr::Expr {
+                    ty: instr::Type::Error,
+                    expr: instr::ExprKind::Application(name.clone(), es)
+                }
+            } else if let Some(func) = find_user_function(functions, name, &tys) {
+                // Key variables are not in scope inside a GLSL helper function,
+                // so any the callee uses are passed through as trailing arguments
+                for &var in &func.vars {
+                    env.use_var(var);
+                    es.push(instr::ExprKind::KeyVar(var));
+                }
+
+                instr::Expr {
+                    ty: func.ret,
+                    expr: instr::ExprKind::Call(name.clone(), es)
+                }
+            } else if let Some(ty) = find_function(name, &tys) {
+                env.use_builtin(name);
+
+                instr::Expr {
                     ty: ty,
                     expr: instr::ExprKind::Application(name.clone(), es)
-                })
+                }
             } else {
-                Err(AnalyseError::InvalidApplication(expr.span))
+                let candidates = candidates(functions, name);
+                env.error(AnalyseError::InvalidApplication(expr.span, name.clone(), tys, candidates));
+
+                instr::Expr {
+                    ty: instr::Type::Error,
+                    expr: instr::ExprKind::Application(name.clone(), es)
+                }
             }
         },
 
         ast::Expr::Vec2(ref exprs) => {
-            let e1 = try!(analyse_expr(env, &exprs.0));
-            let e2 = try!(analyse_expr(env, &exprs.1));
+            let e1 = analyse_expr(env, &exprs.0);
+            let e2 = analyse_expr(env, &exprs.1);
 
-            if e1.ty != e2.ty {
-                Err(AnalyseError::IncorrectTupleTypes(expr.span))
-            } else {
-                Ok(instr::Expr {
-                    ty: instr::Type::Vec2,
-                    expr: instr::ExprKind::Vec2(Box::new((e1.expr, e2.expr)))
-                })
+            if !all_floats(&[e1.ty, e2.ty]) {
+                env.error(AnalyseError::IncorrectTupleTypes(expr.span))
+            }
+
+            instr::Expr {
+                ty: instr::Type::Vec2,
+                expr: instr::ExprKind::Vec2(Box::new((e1.expr, e2.expr)))
             }
         },
 
         ast::Expr::Vec3(ref exprs) => {
-            let e1 = try!(analyse_expr(env, &exprs.0));
-            let e2 = try!(analyse_expr(env, &exprs.1));
-            let e3 = try!(analyse_expr(env, &exprs.2));
+            let e1 = analyse_expr(env, &exprs.0);
+            let e2 = analyse_expr(env, &exprs.1);
+            let e3 = analyse_expr(env, &exprs.2);
 
-            if e1.ty != e2.ty || e1.ty != e3.ty {
-                Err(AnalyseError::IncorrectTupleTypes(expr.span))
-            } else {
-                Ok(instr::Expr {
-                    ty: instr::Type::Vec3,
-                    expr: instr::ExprKind::Vec3(Box::new((e1.expr, e2.expr, e3.expr)))
-                })
+            if !all_floats(&[e1.ty, e2.ty, e3.ty]) {
+                env.error(AnalyseError::IncorrectTupleTypes(expr.span))
             }
-        },
 
-        ast::Expr::BinOp(op @ ast::OpKind::ArithOp(_), ref exprs) => {
-            let e1 = try!(analyse_expr(env, &exprs.0));
-            let e2 = try!(analyse_expr(env, &exprs.1));
+            instr::Expr {
+                ty: instr::Type::Vec3,
+                expr: instr::ExprKind::Vec3(Box::new((e1.expr, e2.expr, e3.expr)))
+            }
+        },
 
-            match (e1.ty, e2.ty) {
-                (instr::Type::Float, ty) | (ty, instr::Type::Float) => Ok(instr::Expr {
-                    ty: ty,
-                    expr: instr::ExprKind::BinOp(op, Box::new((e1.expr, e2.expr)))
-                }),
+        ast::Expr::Vec4(ref exprs) => {
+            let e1 = analyse_expr(env, &exprs.0);
+            let e2 = analyse_expr(env, &exprs.1);
+            let e3 = analyse_expr(env, &exprs.2);
+            let e4 = analyse_expr(env, &exprs.3);
 
-                (ref t, ref u) if *t == *u => Ok(instr::Expr {
-                    ty: t.clone(),
-                    expr: instr::ExprKind::BinOp(op, Box::new((e1.expr, e2.expr)))
-                }),
+            if !all_floats(&[e1.ty, e2.ty, e3.ty, e4.ty]) {
+                env.error(AnalyseError::IncorrectTupleTypes(expr.span))
+            }
 
-                _ => Err(AnalyseError::IncorrectBinOpTypes(expr.span))
+            instr::Expr {
+                ty: instr::Type::Vec4,
+                expr: instr::ExprKind::Vec4(Box::new((e1.expr, e2.expr, e3.expr, e4.expr)))
             }
         },
 
-        ast::Expr::BinOp(op @ ast::OpKind::CmpOp(_), ref exprs) => {
-            let e1 = try!(analyse_expr(env, &exprs.0));
-            let e2 = try!(analyse_expr(env, &exprs.1));
+        ast::Expr::BinOp(op, ref exprs) => {
+            let e1 = analyse_expr(env, &exprs.0);
+            let e2 = analyse_expr(env, &exprs.1);
 
-            if e1.ty == e2.ty {
-                Ok(instr::Expr {
-                    ty: instr::Type::Bool,
-                    expr: instr::ExprKind::BinOp(op, Box::new((e1.expr, e2.expr)))
-                })
+            let ty = if e1.ty == instr::Type::Error || e2.ty == instr::Type::Error {
+                instr::Type::Error
+            } else if let Some(ty) = find_operator(op, e1.ty, e2.ty) {
+                ty
             } else {
-                Err(AnalyseError::IncorrectBinOpTypes(expr.span))
+                env.error(AnalyseError::IncorrectBinOpTypes(expr.span, e1.ty, e2.ty));
+                instr::Type::Error
+            };
+
+            // These lower to GLSL functions without mixed scalar/vector
+            // overloads, so a scalar operand is widened to the result type
+            let operands = match op {
+                ast::OpKind::ArithOp(ast::ArithOpKind::Mod) |
+                ast::OpKind::ArithOp(ast::ArithOpKind::Pow) => (splat(e1, ty), splat(e2, ty)),
+                _ => (e1.expr, e2.expr)
+            };
+
+            instr::Expr {
+                ty: ty,
+                expr: instr::ExprKind::BinOp(op, Box::new(operands))
             }
         },
 
-        ast::Expr::Stmt(_) => unimplemented!()
-    }
-}
+        ast::Expr::UnaryOp(op, ref e) => {
+            let e = analyse_expr(env, e);
 
-/*
+            let ty = if e.ty == instr::Type::Error {
+                instr::Type::Error
+            } else if let Some(ty) = find_unary_operator(op, e.ty) {
+                ty
+            } else {
+                env.error(AnalyseError::IncorrectUnaryOpType(expr.span, e.ty));
+                instr::Type::Error
+            };
 
-    fn expr_type(&self, expr: &ast::Expr) -> Result<instr::Type, AnalyseError> {
-        match expr {
-            &ast::Expr::Literal(_) => Ok(instr::Type::Float),
-            &ast::Expr::Bool(_) => Ok(instr::Type::Bool),
+            instr::Expr {
+                ty: ty,
+                expr: instr::ExprKind::UnaryOp(op, Box::new(e.expr))
+            }
+        },
 
-            &ast::Expr::Var(ref name) => self.lookup(name).ok_or(AnalyseError::UndefinedName(name.clone())),
+        ast::Expr::Swizzle(ref e, ref field) => {
+            let e = analyse_expr(env, e);
 
-            &ast::Expr::Vec2(ref exprs) => {
-                let ty = try!(self.expr_type(&exprs.0.data));
-                if try!(self.expr_type(&exprs.1.data)) == ty {
-                    Ok(instr::Type::Vec2)
-                } else {
-                    Err(AnalyseError::IncorrectTupleTypes(Span { begin: exprs.0.span.begin, end: exprs.1.span.end }))
+            let (ty, components) = match swizzle(e.ty, field) {
+                _ if e.ty == instr::Type::Error => (instr::Type::Error, Vec::new()),
+                Some(swizzled) => swizzled,
+                None => {
+                    env.error(AnalyseError::InvalidSwizzle(expr.span, e.ty, field.clone()));
+                    (instr::Type::Error, Vec::new())
                 }
-            },
+            };
 
-            &ast::Expr::Vec3(ref exprs) => {
-                let ty = try!(self.expr_type(&exprs.0.data));
-                if try!(self.expr_type(&exprs.1.data)) == ty && try!(self.expr_type(&exprs.2.data)) == ty {
-                    Ok(instr::Type::Vec3)
-                } else {
-                    Err(AnalyseError::IncorrectTupleTypes(Span { begin: exprs.0.span.begin, end: exprs.2.span.end }))
-                }
-            },
+            instr::Expr {
+                ty: ty,
+                expr: instr::ExprKind::Swizzle(Box::new(e.expr), components)
+            }
+        },
 
-            &ast::Expr::BinOp(ast::OpKind::ArithOp(_), ref exprs) => {
-                match (try!(self.expr_type(&exprs.0.data)), try!(self.expr_type(&exprs.1.data))) {
-                    (instr::Type::Float, ty) | (ty, instr::Type::Float) => Ok(ty),
-                    (ref a, ref b) if *a == *b => Ok(*a),
-                    _ => Err(AnalyseError::IncorrectBinOpTypes(Span { begin: exprs.0.span.begin, end: exprs.1.span.end }))
-                }
-            },
+        ast::Expr::Stmt(ast::ExprStmt::ITE(ref exprs)) => {
+            let i = analyse_expr(env, &exprs.0);
+            if i.ty != instr::Type::Bool && i.ty != instr::Type::Error {
+                env.error(AnalyseError::ExpectedBoolean(exprs.0.span, i.ty));
+            }
 
-            &ast::Expr::BinOp(ast::OpKind::CmpOp(_), ref exprs) => {
-                match (try!(self.expr_type(&exprs.0.data)), try!(self.expr_type(&exprs.1.data))) {
-                    (ref a, ref b) if *a == *b => Ok(*a),
-                    _ => Err(AnalyseError::IncorrectBinOpTypes(Span { begin: exprs.0.span.begin, end: exprs.1.span.end }))
-                }
-            },
+            // The branches flush their own hoisted instructions, so anything
+            // hoisted so far must be kept back until they have been analysed
+            let hoisted = mem::replace(&mut env.hoisted, Vec::new());
+            let name = env.temp();
 
-            &ast::Expr::Stmt(ast::ExprStmt::ITE(ref ite)) => {
+            let (t, tty) = analyse_branch(env, &exprs.1, &name);
 
-            }
-        }
-    }
+            let ty = if let Some(ref b) = exprs.2 {
+                let (e, ety) = analyse_branch(env, b, &name);
 
-impl ast::AST {
-    pub fn analyse(&self) -> Result<::Shady, AnalyseError> {
-        let mut shady = ::Shady::new();
+                let ty = match (tty, ety) {
+                    (Some(instr::Type::Error), _) | (_, Some(instr::Type::Error)) => instr::Type::Error,
+                    (Some(t), Some(e)) if t != e => {
+                        env.error(AnalyseError::IncorrectBranchTypes(expr.span, t, e));
+                        instr::Type::Error
+                    },
+                    (Some(ty), _) | (_, Some(ty)) => ty,
 
-        for item in &self.0 {
-            shady.push_item(try!(item.data.analyse()));
-        }
+                    // A branch that neither returns nor produces a value has
+                    // already been reported by `analyse_branch`
+                    (None, None) => {
+                        if returns(&t.instrs) && returns(&e.instrs) {
+                            env.error(AnalyseError::NeverProducesValue(expr.span));
+                        }
 
-        Ok(shady)
+                        instr::Type::Error
+                    }
+                };
+
+                env.hoisted = hoisted;
+                env.hoist(instr::Instr::Decl(name.clone(), ty, None), expr.span);
+                env.hoist(instr::Instr::ITE(i.expr, t, Some(e)), expr.span);
+                ty
+            } else {
+                env.error(AnalyseError::ExpectedElse(expr.span));
+                env.hoisted = hoisted;
+                instr::Type::Error
+            };
+
+            instr::Expr {
+                ty: ty,
+                expr: instr::ExprKind::Var(name)
+            }
+        },
     }
 }
 
-impl ast::Item {
-    pub fn analyse(&self) -> Result<instr::Item, AnalyseError> {
-        let mut item = instr::Item::new(self.item);
-        let mut env = Env::new(self.item);
-
-        for stmt in &self.block.data.stmts {
-            match &stmt.data {
-                &ast::Stmt::Assignment(ref name, ref expr) => {
-                    let ty = try!(env.expr_type(&expr.data));
-                    env.insert(name.clone(), ty);
-                    item.push_instr(instr::ass(ty, name.clone(), expr.data.clone()));
-                },
-
-                &ast::Stmt::Return(ref expr) => if try!(env.expr_type(&expr.data)) == item.ret {
-                    item.push_instr(instr::ret(expr.data.clone()));
-                } else {
-                    return Err(AnalyseError::IncorrectReturnType(stmt.span));
-                }
-            }
-        }
+/// The type and component indices selected by a GLSL-style swizzle of a vector,
+/// which may not mix letters from the `xyzw`, `rgba` and `stpq` sets
+fn swizzle(ty: instr::Type, field: &str) -> Option<(instr::Type, Vec<usize>)> {
+    let size = match ty {
+        instr::Type::Vec2 => 2,
+        instr::Type::Vec3 => 3,
+        instr::Type::Vec4 => 4,
+        _ => return None
+    };
 
-        if let Some(ref expr) = self.block.data.expr {
-            if try!(env.expr_type(&expr.data)) == item.ret {
-                item.push_instr(instr::ret(expr.data.clone()));
-            } else {
-                return Err(AnalyseError::IncorrectReturnType(expr.span));
-            }
-        };
+    let set = ["xyzw", "rgba", "stpq"].iter()
+        .find(|set| field.chars().next().is_some_and(|c| set.contains(c)));
 
-        if let Some(&instr::Instr::Return(_)) = item.instrs.0.last() {
-            Ok(item)
-        } else {
-            return Err(AnalyseError::ExpectedReturn(self.block.span))
-        }
+    let components = set.and_then(|set| field.chars().map(|c| set.find(c)).collect::<Option<Vec<_>>>())?;
 
+    if components.iter().any(|&idx| idx >= size) {
+        return None
     }
+
+    Some((match components.len() {
+        1 => instr::Type::Float,
+        2 => instr::Type::Vec2,
+        3 => instr::Type::Vec3,
+        4 => instr::Type::Vec4,
+        _ => return None
+    }, components))
 }
 
-impl instr::Item {
-    fn new(kind: ast::ItemKind) -> instr::Item {
-        match kind {
-            ast::ItemKind::Image => instr::Item {
-                ret: instr::Type::Vec3,
-                kind: ast::ItemKind::Image,
-                instrs: instr::Block(Vec::new())
-            }
-        }
+fn splat(expr: instr::Expr, ty: instr::Type) -> instr::ExprKind {
+    match (expr.ty, ty) {
+        (instr::Type::Float, instr::Type::Vec2) |
+        (instr::Type::Float, instr::Type::Vec3) |
+        (instr::Type::Float, instr::Type::Vec4) => instr::ExprKind::Splat(ty, Box::new(expr.expr)),
+        _ => expr.expr
     }
+}
 
-    fn push_instr(&mut self, instr: instr::Instr) {
-        self.instrs.0.push(instr)
+/// Analyses one branch of a value-producing if, assigning its value to `name`
+fn analyse_branch(env: &mut Env, block: &Spanned<ast::Block>, name: &str) -> (instr::Block, Option<instr::Type>) {
+    let mut ty = None;
+
+    env.push_scope();
+    let b = analyse_block(env, block, Some(&mut |block, env, expr| {
+        let e = analyse_expr(env, expr);
+        flush_hoisted(env, &mut block.instrs, &mut block.ret);
+        ty = Some(e.ty);
+        block.instrs.push(instr::Instr::Assignment(name.to_owned(), e));
+    }));
+    env.pop_scope();
+
+    // A branch that always returns doesn't need to produce a value
+    if ty.is_none() && !returns(&b.instrs) {
+        env.error(AnalyseError::ExpectedValue(block.span));
     }
+
+    (b, ty)
 }
 
-*/
+/// Whether every element of a tuple is a float, ignoring those that already
+/// failed to type
+fn all_floats(tys: &[instr::Type]) -> bool {
+    tys.iter().all(|&ty| ty == instr::Type::Float || ty == instr::Type::Error)
+}
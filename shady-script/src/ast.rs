@@ -9,9 +9,32 @@ pub struct Item {
     pub item: ItemKind
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum ItemKind {
     Image,
+    Function(Signature),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub params: Vec<Spanned<Param>>,
+    pub ret: Type
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Type {
+    Float,
+    Bool,
+    Vec2,
+    Vec3,
+    Vec4
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -29,14 +52,17 @@ pub enum Stmt {
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Expr {
-    KeyVar(KeyVar),
     Literal(String),
+    Colour(Vec<u8>),
     Bool(bool),
     Var(String),
     App(String, Vec<Spanned<Expr>>),
     Vec2(Box<(Spanned<Expr>, Spanned<Expr>)>),
     Vec3(Box<(Spanned<Expr>, Spanned<Expr>, Spanned<Expr>)>),
+    Vec4(Box<(Spanned<Expr>, Spanned<Expr>, Spanned<Expr>, Spanned<Expr>)>),
     BinOp(OpKind, Box<(Spanned<Expr>, Spanned<Expr>)>),
+    UnaryOp(UnaryOpKind, Box<Spanned<Expr>>),
+    Swizzle(Box<Spanned<Expr>>, String),
     Stmt(ExprStmt),
 }
 
@@ -46,7 +72,38 @@ pub enum KeyVar {
     YPos,
     Time,
     MouseX,
-    MouseY
+    MouseY,
+    Width,
+    Height,
+    Aspect,
+    PixelX,
+    PixelY,
+    Frame,
+    DeltaTime,
+    Radius,
+    Theta
+}
+
+impl KeyVar {
+    pub fn from_name(name: &str) -> Option<KeyVar> {
+        match name {
+            "x" => Some(KeyVar::XPos),
+            "y" => Some(KeyVar::YPos),
+            "t" => Some(KeyVar::Time),
+            "mx" => Some(KeyVar::MouseX),
+            "my" => Some(KeyVar::MouseY),
+            "w" => Some(KeyVar::Width),
+            "h" => Some(KeyVar::Height),
+            "aspect" => Some(KeyVar::Aspect),
+            "px" => Some(KeyVar::PixelX),
+            "py" => Some(KeyVar::PixelY),
+            "frame" => Some(KeyVar::Frame),
+            "dt" => Some(KeyVar::DeltaTime),
+            "r" => Some(KeyVar::Radius),
+            "theta" => Some(KeyVar::Theta),
+            _ => None
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -58,6 +115,7 @@ pub enum ExprStmt {
 pub enum OpKind {
     ArithOp(ArithOpKind),
     CmpOp(CmpOpKind),
+    LogicOp(LogicOpKind),
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -66,13 +124,30 @@ pub enum ArithOpKind {
     Sub,
     Mul,
     Div,
+    Mod,
+    Pow,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum CmpOpKind {
     Lt,
     Gt,
+    Le,
+    Ge,
     Eq,
+    Ne,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum LogicOpKind {
+    And,
+    Or,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum UnaryOpKind {
+    Neg,
+    Not,
 }
 
 pub fn image(block: Spanned<Block>) -> Item {
@@ -82,6 +157,24 @@ pub fn image(block: Spanned<Block>) -> Item {
     }
 }
 
+pub fn function<S: Into<String>>(name: S, params: Vec<Spanned<Param>>, ret: Type, block: Spanned<Block>) -> Item {
+    Item {
+        block: block,
+        item: ItemKind::Function(Signature {
+            name: name.into(),
+            params: params,
+            ret: ret
+        })
+    }
+}
+
+pub fn param<S: Into<String>>(name: S, ty: Type) -> Param {
+    Param {
+        name: name.into(),
+        ty: ty
+    }
+}
+
 pub fn block(stmts: Vec<Spanned<Stmt>>, expr: Option<Spanned<Expr>>) -> Block {
     Block {
         stmts: stmts,
@@ -101,6 +194,17 @@ pub fn lit<S: Into<String>>(s: S) -> Expr {
     Expr::Literal(s.into())
 }
 
+/// Takes the hex digits of a colour literal, which the lexer has checked
+pub fn colour(hex: &str) -> Expr {
+    let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).unwrap() as u8).collect();
+
+    // Short forms repeat each digit, so `#f80` is `#ff8800`
+    Expr::Colour(match digits.len() {
+        3 | 4 => digits.iter().map(|&d| d * 17).collect(),
+        _ => digits.chunks(2).map(|pair| pair[0] * 16 + pair[1]).collect()
+    })
+}
+
 pub fn var<S: Into<String>>(s: S) -> Expr {
     Expr::Var(s.into())
 }
@@ -117,6 +221,10 @@ pub fn vec3(a: Spanned<Expr>, b: Spanned<Expr>, c: Spanned<Expr>) -> Expr {
     Expr::Vec3(Box::new((a, b, c)))
 }
 
+pub fn vec4(a: Spanned<Expr>, b: Spanned<Expr>, c: Spanned<Expr>, d: Spanned<Expr>) -> Expr {
+    Expr::Vec4(Box::new((a, b, c, d)))
+}
+
 pub fn add(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
     Expr::BinOp(OpKind::ArithOp(ArithOpKind::Add), Box::new((a, b)))
 }
@@ -133,6 +241,14 @@ pub fn div(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
     Expr::BinOp(OpKind::ArithOp(ArithOpKind::Div), Box::new((a, b)))
 }
 
+pub fn rem(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::ArithOp(ArithOpKind::Mod), Box::new((a, b)))
+}
+
+pub fn pow(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::ArithOp(ArithOpKind::Pow), Box::new((a, b)))
+}
+
 pub fn lt(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
     Expr::BinOp(OpKind::CmpOp(CmpOpKind::Lt), Box::new((a, b)))
 }
@@ -141,10 +257,42 @@ pub fn gt(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
     Expr::BinOp(OpKind::CmpOp(CmpOpKind::Gt), Box::new((a, b)))
 }
 
+pub fn le(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::CmpOp(CmpOpKind::Le), Box::new((a, b)))
+}
+
+pub fn ge(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::CmpOp(CmpOpKind::Ge), Box::new((a, b)))
+}
+
 pub fn eq(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
     Expr::BinOp(OpKind::CmpOp(CmpOpKind::Eq), Box::new((a, b)))
 }
 
+pub fn ne(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::CmpOp(CmpOpKind::Ne), Box::new((a, b)))
+}
+
+pub fn and(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::LogicOp(LogicOpKind::And), Box::new((a, b)))
+}
+
+pub fn or(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::LogicOp(LogicOpKind::Or), Box::new((a, b)))
+}
+
+pub fn swizzle<S: Into<String>>(a: Spanned<Expr>, s: S) -> Expr {
+    Expr::Swizzle(Box::new(a), s.into())
+}
+
+pub fn neg(a: Spanned<Expr>) -> Expr {
+    Expr::UnaryOp(UnaryOpKind::Neg, Box::new(a))
+}
+
+pub fn not(a: Spanned<Expr>) -> Expr {
+    Expr::UnaryOp(UnaryOpKind::Not, Box::new(a))
+}
+
 pub fn ite(i: Spanned<Expr>, t: Spanned<Block>, e: Option<Spanned<Block>>) -> ExprStmt {
     ExprStmt::ITE(Box::new((i, t, e)))
 }
@@ -0,0 +1,352 @@
+//! Colour space conversions and the CSS named colours. Hues are in turns
+//! (0 to 1) and RGB is gamma-encoded sRGB, as an image returns it, unless the
+//! name says linear.
+
+use std::f32::consts::PI;
+
+pub const HUE: &'static str = r#"vec3 _hue(float h) {
+    return clamp(abs(mod(h * 6.0 + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+}"#;
+
+pub const HSV: &'static str = r#"vec3 hsv(float h, float s, float v) {
+    return v * mix(vec3(1.0), _hue(h), s);
+}"#;
+
+pub const HSL: &'static str = r#"vec3 hsl(float h, float s, float l) {
+    return l + s * (_hue(h) - 0.5) * (1.0 - abs(2.0 * l - 1.0));
+}"#;
+
+pub const RGB_HUE: &'static str = r#"float _rgb_hue(vec3 c, float hi, float d) {
+    if (d <= 0.0) {
+        return 0.0;
+    } else if (hi == c.r) {
+        return mod((c.g - c.b) / d, 6.0) / 6.0;
+    } else if (hi == c.g) {
+        return ((c.b - c.r) / d + 2.0) / 6.0;
+    } else {
+        return ((c.r - c.g) / d + 4.0) / 6.0;
+    }
+}"#;
+
+pub const RGB_TO_HSV: &'static str = r#"vec3 rgb_to_hsv(vec3 c) {
+    float hi = max(max(c.r, c.g), c.b);
+    float d = hi - min(min(c.r, c.g), c.b);
+    return vec3(_rgb_hue(c, hi, d), hi > 0.0 ? d / hi : 0.0, hi);
+}"#;
+
+pub const RGB_TO_HSL: &'static str = r#"vec3 rgb_to_hsl(vec3 c) {
+    float hi = max(max(c.r, c.g), c.b);
+    float lo = min(min(c.r, c.g), c.b);
+    float d = hi - lo;
+    float l = (hi + lo) * 0.5;
+    return vec3(_rgb_hue(c, hi, d), d > 0.0 ? d / (1.0 - abs(2.0 * l - 1.0)) : 0.0, l);
+}"#;
+
+pub const SRGB_TO_LINEAR: &'static str = r#"vec3 srgb_to_linear(vec3 c) {
+    return mix(c / 12.92, pow(max((c + 0.055) / 1.055, 0.0), vec3(2.4)), step(0.04045, c));
+}"#;
+
+pub const LINEAR_TO_SRGB: &'static str = r#"vec3 linear_to_srgb(vec3 c) {
+    return mix(c * 12.92, 1.055 * pow(max(c, 0.0), vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}"#;
+
+pub const OKLAB: &'static str = r#"vec3 oklab(float l, float a, float b) {
+    vec3 lms = vec3(
+        l + 0.3963377774 * a + 0.2158037573 * b,
+        l - 0.1055613458 * a - 0.0638541728 * b,
+        l - 0.0894841775 * a - 1.2914855480 * b);
+    lms = lms * lms * lms;
+    return linear_to_srgb(vec3(
+        dot(vec3(4.0767416621, -3.3077115913, 0.2309699292), lms),
+        dot(vec3(-1.2684380046, 2.6097574011, -0.3413193965), lms),
+        dot(vec3(-0.0041960863, -0.7034186147, 1.7076147010), lms)));
+}"#;
+
+pub const RGB_TO_OKLAB: &'static str = r#"vec3 rgb_to_oklab(vec3 c) {
+    c = srgb_to_linear(c);
+    vec3 lms = vec3(
+        dot(vec3(0.4122214708, 0.5363325363, 0.0514459929), c),
+        dot(vec3(0.2119034982, 0.6806995451, 0.1073969566), c),
+        dot(vec3(0.0883024619, 0.2817188376, 0.6299787005), c));
+    lms = sign(lms) * pow(abs(lms), vec3(1.0 / 3.0));
+    return vec3(
+        dot(vec3(0.2104542553, 0.7936177850, -0.0040720468), lms),
+        dot(vec3(1.9779984951, -2.4285922050, 0.4505937099), lms),
+        dot(vec3(0.0259040371, 0.7827717662, -0.8086757660), lms));
+}"#;
+
+pub const OKLCH: &'static str = r#"vec3 oklch(float l, float c, float h) {
+    return oklab(l, c * cos(2.0 * _PI * h), c * sin(2.0 * _PI * h));
+}"#;
+
+pub const RGB_TO_OKLCH: &'static str = r#"vec3 rgb_to_oklch(vec3 c) {
+    vec3 lab = rgb_to_oklab(c);
+    return vec3(lab.x, length(lab.yz), fract(atan(lab.z, lab.y) / (2.0 * _PI)));
+}"#;
+
+pub const PALETTE: &'static str = r#"vec3 palette(float t, vec3 a, vec3 b, vec3 c, vec3 d) {
+    return a + b * cos(2.0 * _PI * (c * t + d));
+}"#;
+
+fn fract(a: f32) -> f32 {
+    a - a.floor()
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn map<F: Fn(f32) -> f32>(c: [f32; 3], f: F) -> [f32; 3] {
+    [f(c[0]), f(c[1]), f(c[2])]
+}
+
+fn hue(h: f32) -> [f32; 3] {
+    let channel = |offset: f32| {
+        let k = h * 6.0 + offset;
+        ((k - 6.0 * (k / 6.0).floor() - 3.0).abs() - 1.0).max(0.0).min(1.0)
+    };
+
+    [channel(0.0), channel(4.0), channel(2.0)]
+}
+
+pub fn hsv(h: f32, s: f32, v: f32) -> [f32; 3] {
+    map(hue(h), |k| v * ((1.0 - s) + k * s))
+}
+
+pub fn hsl(h: f32, s: f32, l: f32) -> [f32; 3] {
+    map(hue(h), |k| l + s * (k - 0.5) * (1.0 - (2.0 * l - 1.0).abs()))
+}
+
+fn rgb_hue(c: [f32; 3], hi: f32, d: f32) -> f32 {
+    if d <= 0.0 {
+        0.0
+    } else if hi == c[0] {
+        let k = (c[1] - c[2]) / d;
+        (k - 6.0 * (k / 6.0).floor()) / 6.0
+    } else if hi == c[1] {
+        ((c[2] - c[0]) / d + 2.0) / 6.0
+    } else {
+        ((c[0] - c[1]) / d + 4.0) / 6.0
+    }
+}
+
+pub fn rgb_to_hsv(c: [f32; 3]) -> [f32; 3] {
+    let hi = c[0].max(c[1]).max(c[2]);
+    let d = hi - c[0].min(c[1]).min(c[2]);
+    [rgb_hue(c, hi, d), if hi > 0.0 { d / hi } else { 0.0 }, hi]
+}
+
+pub fn rgb_to_hsl(c: [f32; 3]) -> [f32; 3] {
+    let hi = c[0].max(c[1]).max(c[2]);
+    let lo = c[0].min(c[1]).min(c[2]);
+    let (d, l) = (hi - lo, (hi + lo) * 0.5);
+    [rgb_hue(c, hi, d), if d > 0.0 { d / (1.0 - (2.0 * l - 1.0).abs()) } else { 0.0 }, l]
+}
+
+pub fn srgb_to_linear(c: [f32; 3]) -> [f32; 3] {
+    map(c, |c| if c < 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) })
+}
+
+pub fn linear_to_srgb(c: [f32; 3]) -> [f32; 3] {
+    map(c, |c| if c < 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 })
+}
+
+pub fn oklab(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let lms = map([
+        l + 0.39633778 * a + 0.21580376 * b,
+        l - 0.105561346 * a - 0.06385417 * b,
+        l - 0.08948418 * a - 1.2914855 * b
+    ], |c| c * c * c);
+
+    linear_to_srgb([
+        dot([4.0767417, -3.3077116, 0.23096994], lms),
+        dot([-1.268438, 2.6097574, -0.34131938], lms),
+        dot([-0.0041960864, -0.7034186, 1.7076147], lms)
+    ])
+}
+
+pub fn rgb_to_oklab(c: [f32; 3]) -> [f32; 3] {
+    let c = srgb_to_linear(c);
+    let lms = map([
+        dot([0.41222146, 0.53633255, 0.051445995], c),
+        dot([0.2119035, 0.6806995, 0.10739696], c),
+        dot([0.08830246, 0.28171885, 0.6299787], c)
+    ], f32::cbrt);
+
+    [
+        dot([0.21045426, 0.7936178, -0.004072047], lms),
+        dot([1.9779985, -2.4285922, 0.4505937], lms),
+        dot([0.025904037, 0.78277177, -0.80867577], lms)
+    ]
+}
+
+pub fn oklch(l: f32, c: f32, h: f32) -> [f32; 3] {
+    oklab(l, c * (2.0 * PI * h).cos(), c * (2.0 * PI * h).sin())
+}
+
+pub fn rgb_to_oklch(c: [f32; 3]) -> [f32; 3] {
+    let lab = rgb_to_oklab(c);
+    [lab[0], (lab[1] * lab[1] + lab[2] * lab[2]).sqrt(), fract(lab[2].atan2(lab[1]) / (2.0 * PI))]
+}
+
+pub fn palette(t: f32, a: [f32; 3], b: [f32; 3], c: [f32; 3], d: [f32; 3]) -> [f32; 3] {
+    let channel = |i: usize| a[i] + b[i] * (2.0 * PI * (c[i] * t + d[i])).cos();
+    [channel(0), channel(1), channel(2)]
+}
+
+/// The CSS named colours, sorted by name
+const NAMED: &'static [(&'static str, [u8; 3])] = &[
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+/// Looks up a CSS named colour
+pub fn named(name: &str) -> Option<[u8; 3]> {
+    NAMED.binary_search_by(|&(n, _)| n.cmp(name)).ok().map(|i| NAMED[i].1)
+}
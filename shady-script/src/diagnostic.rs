@@ -0,0 +1,56 @@
+use std::fmt::Write;
+
+use span::Span;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(span: Span, message: S) -> Diagnostic {
+        Diagnostic {
+            span: span,
+            message: message.into()
+        }
+    }
+
+    /// Renders the diagnostic with a `file:line:col` header, followed by the
+    /// source line the span begins on with the span underlined
+    pub fn render(&self, filename: &str, source: &str) -> String {
+        let begin = self.span.begin.min(source.len());
+        let end = self.span.end.max(begin).min(source.len());
+
+        let line_begin = source[..begin].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let line_end = source[begin..].find('\n').map(|idx| begin + idx).unwrap_or(source.len());
+
+        let (line, col) = line_col(source, begin);
+
+        let text = source[line_begin..line_end].trim_end_matches('\r');
+        let gutter = line.to_string();
+        let padding = " ".repeat(gutter.len());
+
+        // Keep tabs in the indent so the carets line up with the source line
+        let indent = source[line_begin..begin].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let carets = source[begin..end.min(line_end)].chars().count().max(1);
+
+        let mut buffer = String::new();
+        writeln!(buffer, "error: {}", self.message).unwrap();
+        writeln!(buffer, "{}--> {}:{}:{}", padding, filename, line, col).unwrap();
+        writeln!(buffer, "{} |", padding).unwrap();
+        writeln!(buffer, "{} | {}", gutter, text).unwrap();
+        write!(buffer, "{} | {}{}", padding, indent, "^".repeat(carets)).unwrap();
+        buffer
+    }
+}
+
+/// The 1-based line and column of a byte offset into the source
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let line_begin = source[..offset].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+
+    (source[..offset].matches('\n').count() + 1, source[line_begin..offset].chars().count() + 1)
+}
@@ -0,0 +1,57 @@
+//! Domain transforms, which move the point a shape is evaluated at rather
+//! than the shape itself.
+
+use std::f32::consts::PI;
+
+pub const REPEAT: &'static str = r#"float repeat(float p, float period) {
+    return mod(p + 0.5 * period, period) - 0.5 * period;
+}
+
+vec2 repeat(vec2 p, vec2 period) {
+    return mod(p + 0.5 * period, period) - 0.5 * period;
+}
+
+vec2 repeat(vec2 p, float period) {
+    return mod(p + 0.5 * period, period) - 0.5 * period;
+}"#;
+
+pub const MIRROR: &'static str = r#"float mirror(float p) {
+    return abs(p);
+}
+
+vec2 mirror(vec2 p) {
+    return abs(p);
+}"#;
+
+pub const KALEIDO: &'static str = r#"vec2 kaleido(vec2 p, float n) {
+    float an = _PI / n;
+    float a = mod(atan(p.y, p.x) + an, 2.0 * an) - an;
+    return length(p) * vec2(cos(a), abs(sin(a)));
+}"#;
+
+pub const ROTATE: &'static str = r#"vec2 rotate(vec2 p, float angle) {
+    float c = cos(angle);
+    float s = sin(angle);
+    return vec2(c * p.x - s * p.y, s * p.x + c * p.y);
+}"#;
+
+/// Repeats space every `period`, centred so that the cell around the origin
+/// runs from `-period / 2` to `period / 2`
+pub fn repeat(p: f32, period: f32) -> f32 {
+    let a = p + 0.5 * period;
+    a - period * (a / period).floor() - 0.5 * period
+}
+
+/// Folds the plane into a single wedge of `n` mirrored segments around the origin
+pub fn kaleido(p: [f32; 2], n: f32) -> [f32; 2] {
+    let an = PI / n;
+    let a = p[1].atan2(p[0]) + an;
+    let a = a - 2.0 * an * (a / (2.0 * an)).floor() - an;
+    let len = (p[0] * p[0] + p[1] * p[1]).sqrt();
+    [len * a.cos(), len * a.sin().abs()]
+}
+
+pub fn rotate(p: [f32; 2], angle: f32) -> [f32; 2] {
+    let (s, c) = angle.sin_cos();
+    [c * p[0] - s * p[1], s * p[0] + c * p[1]]
+}
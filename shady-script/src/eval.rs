@@ -0,0 +1,403 @@
+use ::{ast, instr, colour, domain, noise, sdf};
+
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Inputs {
+    pub x: f32,
+    pub y: f32,
+    pub t: f32,
+    pub mx: f32,
+    pub my: f32,
+    pub w: f32,
+    pub h: f32,
+    pub frame: u32,
+    pub dt: f32,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Value {
+    Float(f32),
+    Bool(bool),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl Value {
+    fn float(self) -> f32 {
+        match self {
+            Value::Float(f) => f,
+            _ => panic!("Expected float value - this shouldn't happen")
+        }
+    }
+
+    fn bool(self) -> bool {
+        match self {
+            Value::Bool(b) => b,
+            _ => panic!("Expected bool value - this shouldn't happen")
+        }
+    }
+
+    fn components(self) -> Vec<f32> {
+        match self {
+            Value::Float(a) => vec![a],
+            Value::Vec2(a) => a.to_vec(),
+            Value::Vec3(a) => a.to_vec(),
+            Value::Vec4(a) => a.to_vec(),
+            Value::Bool(_) => panic!("Expected numeric value - this shouldn't happen")
+        }
+    }
+
+    fn from_components(c: &[f32]) -> Value {
+        match *c {
+            [a] => Value::Float(a),
+            [a, b] => Value::Vec2([a, b]),
+            [a, b, c] => Value::Vec3([a, b, c]),
+            [a, b, c, d] => Value::Vec4([a, b, c, d]),
+            _ => panic!("Invalid component count - this shouldn't happen")
+        }
+    }
+
+    /// The components of a vector, or a scalar repeated `n` times
+    fn broadcast(self, n: usize) -> Vec<f32> {
+        match self {
+            Value::Float(a) => vec![a; n],
+            v => v.components()
+        }
+    }
+
+    fn map<F: Fn(f32) -> f32>(self, f: F) -> Value {
+        match self {
+            Value::Float(a) => Value::Float(f(a)),
+            Value::Vec2(a) => Value::Vec2([f(a[0]), f(a[1])]),
+            Value::Vec3(a) => Value::Vec3([f(a[0]), f(a[1]), f(a[2])]),
+            Value::Vec4(a) => Value::Vec4([f(a[0]), f(a[1]), f(a[2]), f(a[3])]),
+            Value::Bool(_) => panic!("Expected numeric value - this shouldn't happen")
+        }
+    }
+
+    fn zip<F: Fn(f32, f32) -> f32>(self, other: Value, f: F) -> Value {
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => Value::Float(f(a, b)),
+            (Value::Float(a), v) => v.map(|b| f(a, b)),
+            (v, Value::Float(b)) => v.map(|a| f(a, b)),
+            (Value::Vec2(a), Value::Vec2(b)) => Value::Vec2([f(a[0], b[0]), f(a[1], b[1])]),
+            (Value::Vec3(a), Value::Vec3(b)) => Value::Vec3([f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2])]),
+            (Value::Vec4(a), Value::Vec4(b)) => Value::Vec4([f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2]), f(a[3], b[3])]),
+            _ => panic!("Mismatched operand values - this shouldn't happen")
+        }
+    }
+
+    fn zip3<F: Fn(f32, f32, f32) -> f32>(self, b: Value, c: Value, f: F) -> Value {
+        let n = [self, b, c].iter().map(|v| v.components().len()).max().unwrap();
+        let (a, b, c) = (self.broadcast(n), b.broadcast(n), c.broadcast(n));
+        Value::from_components(&(0..n).map(|i| f(a[i], b[i], c[i])).collect::<Vec<_>>())
+    }
+
+    fn dot(self, other: Value) -> f32 {
+        self.components().iter().zip(other.components()).map(|(a, b)| a * b).sum()
+    }
+}
+
+impl Inputs {
+    /// The position relative to the image centre, with x scaled by the aspect ratio
+    fn centred(&self) -> (f32, f32) {
+        ((self.x - 0.5) * self.aspect(), self.y - 0.5)
+    }
+
+    /// The aspect ratio, or 1 if the resolution is unknown
+    fn aspect(&self) -> f32 {
+        if self.w > 0.0 && self.h > 0.0 {
+            self.w / self.h
+        } else {
+            1.0
+        }
+    }
+
+    /// Stands in for GLSL's `fwidth(d)`, which for a distance `d` in uv units
+    /// is `|dd/dx| / w + |dd/dy| / h`. Neither partial of a true distance
+    /// exceeds 1, so this takes `1 / w + 1 / h`, or 0 if the resolution is unknown.
+    fn pixel_size(&self) -> f32 {
+        if self.w > 0.0 && self.h > 0.0 {
+            1.0 / self.w + 1.0 / self.h
+        } else {
+            0.0
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    shady: &'a ::Shady,
+    inputs: &'a Inputs,
+    vars: HashMap<String, Value>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(shady: &'a ::Shady, inputs: &'a Inputs) -> Evaluator<'a> {
+        Evaluator {
+            shady: shady,
+            inputs: inputs,
+            vars: HashMap::new()
+        }
+    }
+
+    fn run(&mut self, instrs: &[instr::Instr]) -> Option<Value> {
+        for inst in instrs {
+            match *inst {
+                instr::Instr::Decl(ref name, _, Some(ref expr)) => {
+                    let value = self.expr(expr);
+                    self.vars.insert(name.clone(), value);
+                },
+
+                instr::Instr::Decl(_, _, None) => (),
+
+                instr::Instr::Assignment(ref name, ref expr) => {
+                    let value = self.expr(&expr.expr);
+                    self.vars.insert(name.clone(), value);
+                },
+
+                instr::Instr::Return(ref expr) => return Some(self.expr(&expr.expr)),
+
+                instr::Instr::ITE(ref cond, ref t, ref e) => {
+                    let ret = if self.expr(cond).bool() {
+                        self.run(&t.instrs)
+                    } else if let Some(ref e) = *e {
+                        self.run(&e.instrs)
+                    } else {
+                        None
+                    };
+
+                    if ret.is_some() {
+                        return ret
+                    }
+                },
+            }
+        }
+
+        None
+    }
+
+    fn expr(&mut self, expr: &instr::ExprKind) -> Value {
+        match *expr {
+            instr::ExprKind::KeyVar(var) => Value::Float(match var {
+                ast::KeyVar::XPos => self.inputs.x,
+                ast::KeyVar::YPos => self.inputs.y,
+                ast::KeyVar::Time => self.inputs.t,
+                ast::KeyVar::MouseX => self.inputs.mx,
+                ast::KeyVar::MouseY => self.inputs.my,
+                ast::KeyVar::Width => self.inputs.w,
+                ast::KeyVar::Height => self.inputs.h,
+                ast::KeyVar::Aspect => self.inputs.aspect(),
+                ast::KeyVar::PixelX => self.inputs.x * self.inputs.w,
+                ast::KeyVar::PixelY => self.inputs.y * self.inputs.h,
+                ast::KeyVar::Frame => self.inputs.frame as f32,
+                ast::KeyVar::DeltaTime => self.inputs.dt,
+                ast::KeyVar::Radius => {
+                    let (cx, cy) = self.inputs.centred();
+                    (cx * cx + cy * cy).sqrt()
+                },
+                ast::KeyVar::Theta => {
+                    let (cx, cy) = self.inputs.centred();
+                    cy.atan2(cx)
+                },
+            }),
+
+            instr::ExprKind::Literal(value) => Value::Float(value as f32),
+            instr::ExprKind::Bool(b) => Value::Bool(b),
+
+            instr::ExprKind::Colour(ref channels) =>
+                Value::from_components(&channels.iter().map(|&c| c as f32 / 255.0).collect::<Vec<_>>()),
+
+            instr::ExprKind::Var(ref name) => self.vars[name],
+
+            instr::ExprKind::Application(ref name, ref exprs) => {
+                let args = exprs.iter().map(|e| self.expr(e)).collect::<Vec<_>>();
+                builtin(name, &args, self.inputs)
+            },
+
+            instr::ExprKind::Call(ref name, ref exprs) => {
+                let item = self.shady.function(name).expect("Undefined function - this shouldn't happen");
+                let mut callee = Evaluator::new(self.shady, self.inputs);
+
+                // Trailing key variable arguments are read from the inputs directly
+                for (&(ref param, _), e) in item.params.iter().zip(exprs) {
+                    let value = self.expr(e);
+                    callee.vars.insert(param.clone(), value);
+                }
+
+                callee.run(&item.instrs).expect("Function did not return - this shouldn't happen")
+            },
+
+            instr::ExprKind::Vec2(ref exprs) => Value::Vec2([
+                self.expr(&exprs.0).float(),
+                self.expr(&exprs.1).float()
+            ]),
+
+            instr::ExprKind::Vec3(ref exprs) => Value::Vec3([
+                self.expr(&exprs.0).float(),
+                self.expr(&exprs.1).float(),
+                self.expr(&exprs.2).float()
+            ]),
+
+            instr::ExprKind::Vec4(ref exprs) => Value::Vec4([
+                self.expr(&exprs.0).float(),
+                self.expr(&exprs.1).float(),
+                self.expr(&exprs.2).float(),
+                self.expr(&exprs.3).float()
+            ]),
+
+            instr::ExprKind::BinOp(ast::OpKind::LogicOp(op), ref exprs) => {
+                let a = self.expr(&exprs.0).bool();
+
+                // Short-circuit like GLSL does
+                Value::Bool(match op {
+                    ast::LogicOpKind::And => a && self.expr(&exprs.1).bool(),
+                    ast::LogicOpKind::Or => a || self.expr(&exprs.1).bool(),
+                })
+            },
+
+            instr::ExprKind::BinOp(op, ref exprs) => {
+                let a = self.expr(&exprs.0);
+                let b = self.expr(&exprs.1);
+
+                match op {
+                    ast::OpKind::ArithOp(ast::ArithOpKind::Add) => a.zip(b, |a, b| a + b),
+                    ast::OpKind::ArithOp(ast::ArithOpKind::Sub) => a.zip(b, |a, b| a - b),
+                    ast::OpKind::ArithOp(ast::ArithOpKind::Mul) => a.zip(b, |a, b| a * b),
+                    ast::OpKind::ArithOp(ast::ArithOpKind::Div) => a.zip(b, |a, b| a / b),
+                    ast::OpKind::ArithOp(ast::ArithOpKind::Mod) => a.zip(b, glsl_mod),
+                    ast::OpKind::ArithOp(ast::ArithOpKind::Pow) => a.zip(b, f32::powf),
+                    ast::OpKind::CmpOp(ast::CmpOpKind::Lt) => Value::Bool(a.float() < b.float()),
+                    ast::OpKind::CmpOp(ast::CmpOpKind::Gt) => Value::Bool(a.float() > b.float()),
+                    ast::OpKind::CmpOp(ast::CmpOpKind::Le) => Value::Bool(a.float() <= b.float()),
+                    ast::OpKind::CmpOp(ast::CmpOpKind::Ge) => Value::Bool(a.float() >= b.float()),
+                    ast::OpKind::CmpOp(ast::CmpOpKind::Eq) => Value::Bool(a == b),
+                    ast::OpKind::CmpOp(ast::CmpOpKind::Ne) => Value::Bool(a != b),
+                    ast::OpKind::LogicOp(_) => unreachable!()
+                }
+            },
+
+            instr::ExprKind::UnaryOp(ast::UnaryOpKind::Neg, ref expr) => self.expr(expr).map(|a| -a),
+            instr::ExprKind::UnaryOp(ast::UnaryOpKind::Not, ref expr) => Value::Bool(!self.expr(expr).bool()),
+
+            instr::ExprKind::Swizzle(ref expr, ref components) => {
+                let value = self.expr(expr).components();
+                Value::from_components(&components.iter().map(|&idx| value[idx]).collect::<Vec<_>>())
+            },
+
+            instr::ExprKind::Splat(ty, ref expr) => {
+                let a = self.expr(expr).float();
+
+                match ty {
+                    instr::Type::Vec2 => Value::Vec2([a; 2]),
+                    instr::Type::Vec3 => Value::Vec3([a; 3]),
+                    instr::Type::Vec4 => Value::Vec4([a; 4]),
+                    _ => Value::Float(a)
+                }
+            },
+        }
+    }
+}
+
+/// GLSL's `mod`, which takes the sign of the divisor unlike `%` on floats
+fn glsl_mod(a: f32, b: f32) -> f32 {
+    a - b * (a / b).floor()
+}
+
+fn smoothstep(e0: f32, e1: f32, x: f32) -> f32 {
+    let t = ((x - e0) / (e1 - e0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn builtin(name: &str, args: &[Value], inputs: &Inputs) -> Value {
+    match (name, args) {
+        ("sin", &[a]) => a.map(f32::sin),
+        ("cos", &[a]) => a.map(f32::cos),
+        ("tan", &[a]) => a.map(f32::tan),
+        ("asin", &[a]) => a.map(f32::asin),
+        ("acos", &[a]) => a.map(f32::acos),
+        ("atan", &[a]) => a.map(f32::atan),
+        ("atan", &[y, x]) => y.zip(x, f32::atan2),
+        ("pow", &[a, b]) => a.zip(b, f32::powf),
+        ("exp", &[a]) => a.map(f32::exp),
+        ("log", &[a]) => a.map(f32::ln),
+        ("sqrt", &[a]) => a.map(f32::sqrt),
+        ("inversesqrt", &[a]) => a.map(|a| 1.0 / a.sqrt()),
+        ("abs", &[a]) => a.map(f32::abs),
+        ("sign", &[a]) => a.map(|a| if a == 0.0 { 0.0 } else { a.signum() }),
+        ("floor", &[a]) => a.map(f32::floor),
+        ("ceil", &[a]) => a.map(f32::ceil),
+        ("fract", &[a]) => a.map(|a| a - a.floor()),
+        ("mod", &[a, b]) => a.zip(b, glsl_mod),
+        ("min", &[a, b]) => a.zip(b, f32::min),
+        ("max", &[a, b]) => a.zip(b, f32::max),
+        ("clamp", &[a, lo, hi]) => a.zip3(lo, hi, |a, lo, hi| a.max(lo).min(hi)),
+        ("mix", &[a, b, t]) => a.zip3(b, t, |a, b, t| a * (1.0 - t) + b * t),
+        ("step", &[edge, a]) => edge.zip(a, |edge, a| if a < edge { 0.0 } else { 1.0 }),
+        ("smoothstep", &[e0, e1, a]) => e0.zip3(e1, a, smoothstep),
+        ("length", &[a]) => Value::Float(a.dot(a).sqrt()),
+        ("distance", &[a, b]) => {
+            let d = a.zip(b, |a, b| a - b);
+            Value::Float(d.dot(d).sqrt())
+        },
+        ("dot", &[a, b]) => Value::Float(a.dot(b)),
+        ("cross", &[Value::Vec3(a), Value::Vec3(b)]) => Value::Vec3([
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0]
+        ]),
+        ("normalize", &[a]) => {
+            let len = a.dot(a).sqrt();
+            a.map(|a| a / len)
+        },
+        ("reflect", &[i, n]) => {
+            let d = 2.0 * n.dot(i);
+            i.zip(n, |i, n| i - d * n)
+        },
+        ("hash", &[Value::Vec2(p)]) => Value::Float(noise::hash(p)),
+        ("noise2", &[Value::Vec2(p)]) => Value::Float(noise::noise2(p)),
+        ("noise3", &[Value::Vec3(p)]) => Value::Float(noise::noise3(p)),
+        ("fbm", &[Value::Vec2(p), Value::Float(octaves)]) => Value::Float(noise::fbm(p, octaves)),
+        ("voronoi", &[Value::Vec2(p)]) => Value::Float(noise::voronoi(p)),
+        ("sd_circle", &[Value::Vec2(p), Value::Float(r)]) => Value::Float(sdf::sd_circle(p, r)),
+        ("sd_box", &[Value::Vec2(p), Value::Vec2(b)]) => Value::Float(sdf::sd_box(p, b)),
+        ("sd_segment", &[Value::Vec2(p), Value::Vec2(a), Value::Vec2(b)]) => Value::Float(sdf::sd_segment(p, a, b)),
+        ("sd_triangle", &[Value::Vec2(p), Value::Vec2(a), Value::Vec2(b), Value::Vec2(c)]) => Value::Float(sdf::sd_triangle(p, a, b, c)),
+        ("sd_ngon", &[Value::Vec2(p), Value::Float(r), Value::Float(n)]) => Value::Float(sdf::sd_ngon(p, r, n)),
+        ("op_union", &[a, b]) => a.zip(b, f32::min),
+        ("op_smooth_union", &[a, b, k]) => a.zip3(b, k, sdf::op_smooth_union),
+        ("op_subtract", &[a, b]) => a.zip(b, |a, b| a.max(-b)),
+        ("op_intersect", &[a, b]) => a.zip(b, f32::max),
+        ("fill", &[Value::Float(d), Value::Vec3(colour)]) => Value::Vec4(sdf::fill(d, colour, inputs.pixel_size())),
+        ("stroke", &[Value::Float(d), Value::Float(width)]) => Value::Float(sdf::stroke(d, width, inputs.pixel_size())),
+        ("hsv", &[Value::Float(h), Value::Float(s), Value::Float(v)]) => Value::Vec3(colour::hsv(h, s, v)),
+        ("hsl", &[Value::Float(h), Value::Float(s), Value::Float(l)]) => Value::Vec3(colour::hsl(h, s, l)),
+        ("rgb_to_hsv", &[Value::Vec3(c)]) => Value::Vec3(colour::rgb_to_hsv(c)),
+        ("rgb_to_hsl", &[Value::Vec3(c)]) => Value::Vec3(colour::rgb_to_hsl(c)),
+        ("srgb_to_linear", &[Value::Vec3(c)]) => Value::Vec3(colour::srgb_to_linear(c)),
+        ("linear_to_srgb", &[Value::Vec3(c)]) => Value::Vec3(colour::linear_to_srgb(c)),
+        ("oklab", &[Value::Float(l), Value::Float(a), Value::Float(b)]) => Value::Vec3(colour::oklab(l, a, b)),
+        ("oklch", &[Value::Float(l), Value::Float(c), Value::Float(h)]) => Value::Vec3(colour::oklch(l, c, h)),
+        ("rgb_to_oklab", &[Value::Vec3(c)]) => Value::Vec3(colour::rgb_to_oklab(c)),
+        ("rgb_to_oklch", &[Value::Vec3(c)]) => Value::Vec3(colour::rgb_to_oklch(c)),
+        ("palette", &[Value::Float(t), Value::Vec3(a), Value::Vec3(b), Value::Vec3(c), Value::Vec3(d)]) =>
+            Value::Vec3(colour::palette(t, a, b, c, d)),
+        ("repeat", &[p, period]) => p.zip(period, domain::repeat),
+        ("mirror", &[p]) => p.map(f32::abs),
+        ("kaleido", &[Value::Vec2(p), Value::Float(n)]) => Value::Vec2(domain::kaleido(p, n)),
+        ("rotate", &[Value::Vec2(p), Value::Float(angle)]) => Value::Vec2(domain::rotate(p, angle)),
+        _ => panic!("Unknown builtin {} - this shouldn't happen", name)
+    }
+}
+
+impl instr::Item {
+    /// Evaluates an image to an RGBA colour, with opaque alpha for a `vec3` image
+    pub fn evaluate(&self, shady: &::Shady, inputs: &Inputs) -> [f32; 4] {
+        match Evaluator::new(shady, inputs).run(&self.instrs) {
+            Some(Value::Vec3([r, g, b])) => [r, g, b, 1.0],
+            Some(Value::Vec4(colour)) => colour,
+            _ => panic!("Image did not return a colour - this shouldn't happen")
+        }
+    }
+}
@@ -1,37 +1,309 @@
+use ast::{KeyVar, OpKind, ArithOpKind, CmpOpKind, LogicOpKind, UnaryOpKind};
 use instr::Type;
+use colour;
+use domain;
+use noise;
+use sdf;
+
+use std::fmt;
+use std::collections::BTreeSet;
+
+/// A type in a builtin signature, where `Gen` is GLSL's genType: any of
+/// float, vec2, vec3 or vec4, but the same one everywhere in the signature
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ArgType {
+    Exact(Type),
+    Gen,
+}
 
 pub struct Function {
     pub name: &'static str,
-    pub args: &'static [Type],
+    pub args: &'static [ArgType],
+    pub ret: ArgType,
+    /// GLSL definitions the function needs, in dependency order, or
+    /// nothing for a GLSL intrinsic
+    pub prelude: &'static [&'static str],
+}
+
+pub struct Operator {
+    pub op: OpKind,
+    pub sigs: &'static [(Type, Type, Type)],
+}
+
+pub struct UserFunction {
+    pub name: String,
+    pub args: Vec<Type>,
     pub ret: Type,
+    pub vars: BTreeSet<KeyVar>,
+}
+
+/// Shared by the preludes that need pi, so that they all agree on its value
+/// with each other and with `std::f32::consts::PI` on the CPU
+pub const PI: &'static str = "#define _PI 3.14159265358979";
+
+macro_rules! arg {
+    (Gen) => (ArgType::Gen);
+    ($ty:ident) => (ArgType::Exact(Type::$ty));
 }
 
 macro_rules! functions {
-    ($($name:ident($($arg:ident),*) -> $ret:ident;)+) => {
+    ($($name:ident($($arg:ident),*) -> $ret:ident $(= [$($prelude:expr),*])*;)+) => {
         static FUNCTIONS: &'static [Function] = &[
             $(Function {
                 name: stringify!($name),
-                args: &[$(Type::$arg),*],
-                ret: Type::$ret
+                args: &[$(arg!($arg)),*],
+                ret: arg!($ret),
+                prelude: &[$($($prelude),*),*]
             }),+
         ];
     };
 }
 
 functions! {
-    sin(Float) -> Float;
-    cos(Float) -> Float;
-    tan(Float) -> Float;
-    min(Float, Float) -> Float;
-    max(Float, Float) -> Float;
+    sin(Gen) -> Gen;
+    cos(Gen) -> Gen;
+    tan(Gen) -> Gen;
+    asin(Gen) -> Gen;
+    acos(Gen) -> Gen;
+    atan(Gen) -> Gen;
+    atan(Gen, Gen) -> Gen;
+    pow(Gen, Gen) -> Gen;
+    exp(Gen) -> Gen;
+    log(Gen) -> Gen;
+    sqrt(Gen) -> Gen;
+    inversesqrt(Gen) -> Gen;
+    abs(Gen) -> Gen;
+    sign(Gen) -> Gen;
+    floor(Gen) -> Gen;
+    ceil(Gen) -> Gen;
+    fract(Gen) -> Gen;
+    mod(Gen, Gen) -> Gen;
+    mod(Gen, Float) -> Gen;
+    min(Gen, Gen) -> Gen;
+    min(Gen, Float) -> Gen;
+    max(Gen, Gen) -> Gen;
+    max(Gen, Float) -> Gen;
+    clamp(Gen, Gen, Gen) -> Gen;
+    clamp(Gen, Float, Float) -> Gen;
+    mix(Gen, Gen, Gen) -> Gen;
+    mix(Gen, Gen, Float) -> Gen;
+    step(Gen, Gen) -> Gen;
+    step(Float, Gen) -> Gen;
+    smoothstep(Gen, Gen, Gen) -> Gen;
+    smoothstep(Float, Float, Gen) -> Gen;
+    length(Gen) -> Float;
+    distance(Gen, Gen) -> Float;
+    dot(Gen, Gen) -> Float;
+    cross(Vec3, Vec3) -> Vec3;
+    normalize(Gen) -> Gen;
+    reflect(Gen, Gen) -> Gen;
+    hash(Vec2) -> Float = [noise::HASH];
+    noise2(Vec2) -> Float = [noise::HASH, noise::NOISE2];
+    noise3(Vec3) -> Float = [noise::HASH3, noise::NOISE3];
+    fbm(Vec2, Float) -> Float = [noise::HASH, noise::NOISE2, noise::FBM];
+    voronoi(Vec2) -> Float = [noise::HASH, noise::VORONOI];
+    sd_circle(Vec2, Float) -> Float = [sdf::SD_CIRCLE];
+    sd_box(Vec2, Vec2) -> Float = [sdf::SD_BOX];
+    sd_segment(Vec2, Vec2, Vec2) -> Float = [sdf::SD_SEGMENT];
+    sd_triangle(Vec2, Vec2, Vec2, Vec2) -> Float = [sdf::SD_TRIANGLE];
+    sd_ngon(Vec2, Float, Float) -> Float = [PI, sdf::SD_NGON];
+    op_union(Float, Float) -> Float = [sdf::OP_UNION];
+    op_smooth_union(Float, Float, Float) -> Float = [sdf::OP_SMOOTH_UNION];
+    op_subtract(Float, Float) -> Float = [sdf::OP_SUBTRACT];
+    op_intersect(Float, Float) -> Float = [sdf::OP_INTERSECT];
+    fill(Float, Vec3) -> Vec4 = [sdf::FILL];
+    stroke(Float, Float) -> Float = [sdf::STROKE];
+    hsv(Float, Float, Float) -> Vec3 = [colour::HUE, colour::HSV];
+    hsl(Float, Float, Float) -> Vec3 = [colour::HUE, colour::HSL];
+    rgb_to_hsv(Vec3) -> Vec3 = [colour::RGB_HUE, colour::RGB_TO_HSV];
+    rgb_to_hsl(Vec3) -> Vec3 = [colour::RGB_HUE, colour::RGB_TO_HSL];
+    srgb_to_linear(Vec3) -> Vec3 = [colour::SRGB_TO_LINEAR];
+    linear_to_srgb(Vec3) -> Vec3 = [colour::LINEAR_TO_SRGB];
+    oklab(Float, Float, Float) -> Vec3 = [colour::LINEAR_TO_SRGB, colour::OKLAB];
+    oklch(Float, Float, Float) -> Vec3 = [PI, colour::LINEAR_TO_SRGB, colour::OKLAB, colour::OKLCH];
+    rgb_to_oklab(Vec3) -> Vec3 = [colour::SRGB_TO_LINEAR, colour::RGB_TO_OKLAB];
+    rgb_to_oklch(Vec3) -> Vec3 = [PI, colour::SRGB_TO_LINEAR, colour::RGB_TO_OKLAB, colour::RGB_TO_OKLCH];
+    palette(Float, Vec3, Vec3, Vec3, Vec3) -> Vec3 = [PI, colour::PALETTE];
+    repeat(Float, Float) -> Float = [domain::REPEAT];
+    repeat(Vec2, Vec2) -> Vec2 = [domain::REPEAT];
+    repeat(Vec2, Float) -> Vec2 = [domain::REPEAT];
+    mirror(Float) -> Float = [domain::MIRROR];
+    mirror(Vec2) -> Vec2 = [domain::MIRROR];
+    kaleido(Vec2, Float) -> Vec2 = [PI, domain::KALEIDO];
+    rotate(Vec2, Float) -> Vec2 = [domain::ROTATE];
 }
 
-pub fn find_function(name: &str, args: &[Type]) -> Option<Type> {
-    for f in FUNCTIONS {
-        if f.name == name && f.args == args {
-            return Some(f.ret)
+macro_rules! signatures {
+    ($($lhs:ident, $rhs:ident -> $ret:ident;)+) => {
+        &[$((Type::$lhs, Type::$rhs, Type::$ret)),+]
+    };
+}
+
+macro_rules! operators {
+    ($($op:expr => $sigs:expr;)+) => {
+        static OPERATORS: &'static [Operator] = &[
+            $(Operator {
+                op: $op,
+                sigs: $sigs
+            }),+
+        ];
+    };
+}
+
+/// Componentwise arithmetic, with a scalar operand broadcast over a vector
+const ARITHMETIC: &'static [(Type, Type, Type)] = signatures! {
+    Float, Float -> Float;
+    Vec2, Vec2 -> Vec2;
+    Vec3, Vec3 -> Vec3;
+    Vec4, Vec4 -> Vec4;
+    Float, Vec2 -> Vec2;
+    Vec2, Float -> Vec2;
+    Float, Vec3 -> Vec3;
+    Vec3, Float -> Vec3;
+    Float, Vec4 -> Vec4;
+    Vec4, Float -> Vec4;
+};
+
+/// GLSL only defines the relational operators on scalars
+const ORDERING: &'static [(Type, Type, Type)] = signatures! {
+    Float, Float -> Bool;
+};
+
+/// Vectors compare equal when every component does
+const EQUALITY: &'static [(Type, Type, Type)] = signatures! {
+    Float, Float -> Bool;
+    Bool, Bool -> Bool;
+    Vec2, Vec2 -> Bool;
+    Vec3, Vec3 -> Bool;
+    Vec4, Vec4 -> Bool;
+};
+
+const LOGIC: &'static [(Type, Type, Type)] = signatures! {
+    Bool, Bool -> Bool;
+};
+
+operators! {
+    OpKind::ArithOp(ArithOpKind::Add) => ARITHMETIC;
+    OpKind::ArithOp(ArithOpKind::Sub) => ARITHMETIC;
+    OpKind::ArithOp(ArithOpKind::Mul) => ARITHMETIC;
+    OpKind::ArithOp(ArithOpKind::Div) => ARITHMETIC;
+    OpKind::ArithOp(ArithOpKind::Mod) => ARITHMETIC;
+    OpKind::ArithOp(ArithOpKind::Pow) => ARITHMETIC;
+    OpKind::CmpOp(CmpOpKind::Lt) => ORDERING;
+    OpKind::CmpOp(CmpOpKind::Gt) => ORDERING;
+    OpKind::CmpOp(CmpOpKind::Le) => ORDERING;
+    OpKind::CmpOp(CmpOpKind::Ge) => ORDERING;
+    OpKind::CmpOp(CmpOpKind::Eq) => EQUALITY;
+    OpKind::CmpOp(CmpOpKind::Ne) => EQUALITY;
+    OpKind::LogicOp(LogicOpKind::And) => LOGIC;
+    OpKind::LogicOp(LogicOpKind::Or) => LOGIC;
+}
+
+pub fn find_operator(op: OpKind, lhs: Type, rhs: Type) -> Option<Type> {
+    for o in OPERATORS {
+        if o.op == op {
+            for &(l, r, ret) in o.sigs {
+                if l == lhs && r == rhs {
+                    return Some(ret)
+                }
+            }
         }
     }
 
     None
 }
+
+pub fn find_unary_operator(op: UnaryOpKind, ty: Type) -> Option<Type> {
+    match (op, ty) {
+        (UnaryOpKind::Neg, Type::Float) |
+        (UnaryOpKind::Neg, Type::Vec2) |
+        (UnaryOpKind::Neg, Type::Vec3) |
+        (UnaryOpKind::Neg, Type::Vec4) |
+        (UnaryOpKind::Not, Type::Bool) => Some(ty),
+        _ => None
+    }
+}
+
+impl Function {
+    /// The return type when applied to `args`, binding `Gen` to the first
+    /// generic argument and requiring every other generic argument to match it
+    fn apply(&self, args: &[Type]) -> Option<Type> {
+        if self.args.len() != args.len() {
+            return None
+        }
+
+        let mut gen = None;
+
+        for (&param, &arg) in self.args.iter().zip(args) {
+            match param {
+                ArgType::Exact(ty) => if ty != arg {
+                    return None
+                },
+
+                ArgType::Gen => match (gen, arg) {
+                    (None, Type::Float) |
+                    (None, Type::Vec2) |
+                    (None, Type::Vec3) |
+                    (None, Type::Vec4) => gen = Some(arg),
+                    (Some(ty), _) if ty == arg => (),
+                    _ => return None
+                },
+            }
+        }
+
+        match self.ret {
+            ArgType::Exact(ty) => Some(ty),
+            ArgType::Gen => gen
+        }
+    }
+}
+
+impl fmt::Display for ArgType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ArgType::Exact(ty) => write!(f, "{}", ty),
+            ArgType::Gen => write!(f, "genType"),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let args = self.args.iter().map(|ty| ty.to_string()).collect::<Vec<_>>();
+        write!(f, "{}({}) -> {}", self.name, args.join(", "), self.ret)
+    }
+}
+
+impl fmt::Display for UserFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let args = self.args.iter().map(|ty| ty.to_string()).collect::<Vec<_>>();
+        write!(f, "{}({}) -> {}", self.name, args.join(", "), self.ret)
+    }
+}
+
+pub fn find_function(name: &str, args: &[Type]) -> Option<Type> {
+    FUNCTIONS.iter()
+        .filter(|f| f.name == name)
+        .filter_map(|f| f.apply(args))
+        .next()
+}
+
+pub fn find_user_function<'a>(functions: &'a [UserFunction], name: &str, args: &[Type]) -> Option<&'a UserFunction> {
+    functions.iter().find(|f| f.name == name && f.args == args)
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    FUNCTIONS.iter().any(|f| f.name == name)
+}
+
+/// The GLSL definitions needed to call the builtin `name`
+pub fn prelude(name: &str) -> &'static [&'static str] {
+    FUNCTIONS.iter().find(|f| f.name == name).map(|f| f.prelude).unwrap_or(&[])
+}
+
+/// The signatures of every user function and builtin called `name`, for reporting a failed call
+pub fn candidates(functions: &[UserFunction], name: &str) -> Vec<String> {
+    functions.iter().filter(|f| f.name == name).map(|f| f.to_string())
+        .chain(FUNCTIONS.iter().filter(|f| f.name == name).map(|f| f.to_string()))
+        .collect()
+}
@@ -1,6 +1,7 @@
 use std::fmt::{self, Write};
+use std::collections::BTreeSet;
 
-use ::{ast, instr};
+use ::{ast, instr, functions};
 
 pub struct Image<'a>(&'a ::Shady, usize);
 
@@ -9,6 +10,10 @@ pub enum Uniform {
     Time,
     MouseX,
     MouseY,
+    Width,
+    Height,
+    Frame,
+    DeltaTime,
 }
 
 impl<'a> Image<'a> {
@@ -16,13 +21,30 @@ impl<'a> Image<'a> {
         Image(shady, idx)
     }
 
+    pub fn evaluate(&self, inputs: &::Inputs) -> [f32; 4] {
+        self.0.get(self.1).evaluate(self.0, inputs)
+    }
+
     pub fn standalone_uniforms(&self) -> Vec<Uniform> {
-        self.0.get(self.1).vars.iter().filter_map(|var| match var {
-            &ast::KeyVar::Time => Some(Uniform::Time),
-            &ast::KeyVar::MouseX => Some(Uniform::MouseX),
-            &ast::KeyVar::MouseY => Some(Uniform::MouseY),
-            _ => None
-        }).collect()
+        let mut uniforms = BTreeSet::new();
+
+        for var in &self.0.get(self.1).vars {
+            match *var {
+                ast::KeyVar::XPos | ast::KeyVar::YPos => (),
+                ast::KeyVar::Time => { uniforms.insert(Uniform::Time); },
+                ast::KeyVar::MouseX => { uniforms.insert(Uniform::MouseX); },
+                ast::KeyVar::MouseY => { uniforms.insert(Uniform::MouseY); },
+                ast::KeyVar::Width | ast::KeyVar::PixelX => { uniforms.insert(Uniform::Width); },
+                ast::KeyVar::Height | ast::KeyVar::PixelY => { uniforms.insert(Uniform::Height); },
+                ast::KeyVar::Aspect |
+                ast::KeyVar::Radius |
+                ast::KeyVar::Theta => uniforms.extend(&[Uniform::Width, Uniform::Height]),
+                ast::KeyVar::Frame => { uniforms.insert(Uniform::Frame); },
+                ast::KeyVar::DeltaTime => { uniforms.insert(Uniform::DeltaTime); },
+            }
+        }
+
+        uniforms.into_iter().collect()
     }
 
     pub fn standalone_shader(&self) -> String {
@@ -32,18 +54,56 @@ impl<'a> Image<'a> {
                 Uniform::Time => writeln!(uniform_buffer, "uniform float time;").unwrap(),
                 Uniform::MouseX => writeln!(uniform_buffer, "uniform float mouse_x;").unwrap(),
                 Uniform::MouseY => writeln!(uniform_buffer, "uniform float mouse_y;").unwrap(),
+                Uniform::Width => writeln!(uniform_buffer, "uniform float width;").unwrap(),
+                Uniform::Height => writeln!(uniform_buffer, "uniform float height;").unwrap(),
+                Uniform::Frame => writeln!(uniform_buffer, "uniform int frame;").unwrap(),
+                Uniform::DeltaTime => writeln!(uniform_buffer, "uniform float delta_time;").unwrap(),
             }
         }
 
+        // The image takes every key variable it uses, some derived from the uniforms
         let mut arg_buffer = "uv.x, uv.y".to_owned();
-        for uniform in self.standalone_uniforms().iter() {
-            match *uniform {
-                Uniform::Time => write!(arg_buffer, ", time").unwrap(),
-                Uniform::MouseX => write!(arg_buffer, ", mouse_x").unwrap(),
-                Uniform::MouseY => write!(arg_buffer, ", mouse_y").unwrap(),
+        for var in &self.0.get(self.1).vars {
+            match *var {
+                ast::KeyVar::XPos | ast::KeyVar::YPos => (),
+                ast::KeyVar::Time => write!(arg_buffer, ", time").unwrap(),
+                ast::KeyVar::MouseX => write!(arg_buffer, ", mouse_x").unwrap(),
+                ast::KeyVar::MouseY => write!(arg_buffer, ", mouse_y").unwrap(),
+                ast::KeyVar::Width => write!(arg_buffer, ", width").unwrap(),
+                ast::KeyVar::Height => write!(arg_buffer, ", height").unwrap(),
+                ast::KeyVar::Aspect => write!(arg_buffer, ", width / height").unwrap(),
+                ast::KeyVar::PixelX => write!(arg_buffer, ", uv.x * width").unwrap(),
+                ast::KeyVar::PixelY => write!(arg_buffer, ", uv.y * height").unwrap(),
+                ast::KeyVar::Frame => write!(arg_buffer, ", float(frame)").unwrap(),
+                ast::KeyVar::DeltaTime => write!(arg_buffer, ", delta_time").unwrap(),
+                ast::KeyVar::Radius => write!(arg_buffer, ", length(vec2((uv.x - 0.5) * width / height, uv.y - 0.5))").unwrap(),
+                ast::KeyVar::Theta => write!(arg_buffer, ", atan(uv.y - 0.5, (uv.x - 0.5) * width / height)").unwrap(),
             }
         }
 
+        let image = self.0.get(self.1);
+
+        let mut preludes = Vec::new();
+        let mut function_buffer = String::new();
+
+        self.0.with_functions(|item| {
+            add_preludes(&mut preludes, item);
+            writeln!(function_buffer, "{}\n", item.shader_function()).unwrap()
+        });
+
+        add_preludes(&mut preludes, image);
+
+        let mut prelude_buffer = String::new();
+        for prelude in preludes {
+            writeln!(prelude_buffer, "{}\n", prelude).unwrap();
+        }
+
+        // An opaque image only returns a vec3, so fill in the alpha
+        let colour = match image.ret {
+            instr::Type::Vec4 => format!("image({})", arg_buffer),
+            _ => format!("vec4(image({}), 1)", arg_buffer)
+        };
+
         format!(
             r#"#version 330 core
 
@@ -52,33 +112,62 @@ in vec2 uv;
 out vec4 colour;
 
 {}
-{}
+{}{}{}
 
 void main() {{
-    colour = vec4(image({}), 1);
+    colour = {};
 }}"#, 
             uniform_buffer, 
-            self.0.get(self.1).shader_function(&self.standalone_uniforms()),
-            arg_buffer
+            prelude_buffer,
+            function_buffer,
+            image.shader_function(),
+            colour
         )
     }
 }
 
+/// Adds the GLSL definitions of the builtins an item uses, which must come
+/// ahead of any caller and only be defined once even when shared
+fn add_preludes(preludes: &mut Vec<&'static str>, item: &instr::Item) {
+    for name in &item.builtins {
+        for prelude in functions::prelude(name) {
+            if !preludes.contains(prelude) {
+                preludes.push(prelude);
+            }
+        }
+    }
+}
+
 struct InstrVec<'a>(&'a Vec<instr::Instr>);
 struct ExprVec<'a>(&'a Vec<instr::ExprKind>);
 
+/// A float literal, written so that GLSL reads it back as the same float
+struct Literal(f64);
+
 impl instr::Item {
-    fn shader_function(&self, uniforms: &[Uniform]) -> String {
-        let mut arg_buffer = "float x, float y".to_owned();
-        for uniform in uniforms {
-            match *uniform {
-                Uniform::Time => write!(arg_buffer, ", float t").unwrap(),
-                Uniform::MouseX => write!(arg_buffer, ", float mx").unwrap(),
-                Uniform::MouseY => write!(arg_buffer, ", float my").unwrap()
-            }
-        }
+    fn shader_function(&self) -> String {
+        match self.kind {
+            ast::ItemKind::Image => {
+                let mut arg_buffer = "float x, float y".to_owned();
+                for var in self.vars.iter().filter(|&&var| var != ast::KeyVar::XPos && var != ast::KeyVar::YPos) {
+                    write!(arg_buffer, ", float {}", instr::ExprKind::KeyVar(*var)).unwrap();
+                }
+
+                format!("{} image({}) {{\n{}}}", self.ret, arg_buffer, InstrVec(&self.instrs))
+            },
 
-        format!("vec3 image({}) {{\n{}}}", arg_buffer, InstrVec(&self.instrs))
+            ast::ItemKind::Function(ref sig) => {
+                let mut args = self.params.iter()
+                    .map(|&(ref name, ty)| format!("{} {}", ty, name))
+                    .collect::<Vec<_>>();
+
+                for var in &self.vars {
+                    args.push(format!("float {}", instr::ExprKind::KeyVar(*var)));
+                }
+
+                format!("{} fn_{}({}) {{\n{}}}", self.ret, sig.name, args.join(", "), InstrVec(&self.instrs))
+            },
+        }
     }
 }
 
@@ -118,6 +207,19 @@ impl<'a> fmt::Display for ExprVec<'a> {
     }
 }
 
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        // Debug gives the shortest digits that round-trip, but drops the point
+        // from exponent forms like `1e-7`, which GLSL needs to see a float
+        let digits = format!("{:?}", self.0);
+
+        match digits.find('e') {
+            Some(idx) if !digits.contains('.') => write!(f, "{}.0{}", &digits[..idx], &digits[idx..]),
+            _ => write!(f, "{}", digits)
+        }
+    }
+}
+
 impl fmt::Display for instr::Instr {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
@@ -142,6 +244,8 @@ impl fmt::Display for instr::Type {
             &instr::Type::Float => write!(f, "float"),
             &instr::Type::Vec2 => write!(f, "vec2"),
             &instr::Type::Vec3 => write!(f, "vec3"),
+            &instr::Type::Vec4 => write!(f, "vec4"),
+            &instr::Type::Error => write!(f, "<error>"),
         }
     }
 }
@@ -154,19 +258,51 @@ impl fmt::Display for instr::ExprKind {
             &instr::ExprKind::KeyVar(ast::KeyVar::Time) => write!(f, "t"),
             &instr::ExprKind::KeyVar(ast::KeyVar::MouseX) => write!(f, "mx"),
             &instr::ExprKind::KeyVar(ast::KeyVar::MouseY) => write!(f, "my"),
-            &instr::ExprKind::Literal(ref s) => write!(f, "{}", s),
+            &instr::ExprKind::KeyVar(ast::KeyVar::Width) => write!(f, "w"),
+            &instr::ExprKind::KeyVar(ast::KeyVar::Height) => write!(f, "h"),
+            &instr::ExprKind::KeyVar(ast::KeyVar::Aspect) => write!(f, "aspect"),
+            &instr::ExprKind::KeyVar(ast::KeyVar::PixelX) => write!(f, "px"),
+            &instr::ExprKind::KeyVar(ast::KeyVar::PixelY) => write!(f, "py"),
+            &instr::ExprKind::KeyVar(ast::KeyVar::Frame) => write!(f, "frame"),
+            &instr::ExprKind::KeyVar(ast::KeyVar::DeltaTime) => write!(f, "dt"),
+            &instr::ExprKind::KeyVar(ast::KeyVar::Radius) => write!(f, "r"),
+            &instr::ExprKind::KeyVar(ast::KeyVar::Theta) => write!(f, "theta"),
+            &instr::ExprKind::Literal(value) => write!(f, "{}", Literal(value)),
             &instr::ExprKind::Bool(ref b) => write!(f, "{}", b),
+
+            &instr::ExprKind::Colour(ref channels) => {
+                let channels = channels.iter().map(|&c| format!("{:?}", c as f32 / 255.0)).collect::<Vec<_>>();
+                write!(f, "vec{}({})", channels.len(), channels.join(", "))
+            },
+
             &instr::ExprKind::Var(ref s) => write!(f, "{}", s),
             &instr::ExprKind::Application(ref name, ref exprs) => write!(f, "{}({})", name, ExprVec(exprs)),
+            &instr::ExprKind::Call(ref name, ref exprs) => write!(f, "fn_{}({})", name, ExprVec(exprs)),
             &instr::ExprKind::Vec2(ref exprs) => write!(f, "vec2({}, {})", exprs.0, exprs.1),
             &instr::ExprKind::Vec3(ref exprs) => write!(f, "vec3({}, {}, {})", exprs.0, exprs.1, exprs.2),
-            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Add), ref exprs) => write!(f, "{} + {}", exprs.0, exprs.1),
-            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Sub), ref exprs) => write!(f, "{} - {}", exprs.0, exprs.1),
+            &instr::ExprKind::Vec4(ref exprs) => write!(f, "vec4({}, {}, {}, {})", exprs.0, exprs.1, exprs.2, exprs.3),
+
+            &instr::ExprKind::Swizzle(ref expr, ref components) => {
+                let field = components.iter().map(|&idx| ['x', 'y', 'z', 'w'][idx]).collect::<String>();
+                write!(f, "({}).{}", expr, field)
+            },
+            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Add), ref exprs) => write!(f, "({}) + ({})", exprs.0, exprs.1),
+            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Sub), ref exprs) => write!(f, "({}) - ({})", exprs.0, exprs.1),
             &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Mul), ref exprs) => write!(f, "({}) * ({})", exprs.0, exprs.1),
             &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Div), ref exprs) => write!(f, "({}) / ({})", exprs.0, exprs.1),
+            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Mod), ref exprs) => write!(f, "mod({}, {})", exprs.0, exprs.1),
+            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Pow), ref exprs) => write!(f, "pow({}, {})", exprs.0, exprs.1),
             &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Lt), ref exprs) => write!(f, "({}) < ({})", exprs.0, exprs.1),
             &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Gt), ref exprs) => write!(f, "({}) > ({})", exprs.0, exprs.1),
+            &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Le), ref exprs) => write!(f, "({}) <= ({})", exprs.0, exprs.1),
+            &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Ge), ref exprs) => write!(f, "({}) >= ({})", exprs.0, exprs.1),
             &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Eq), ref exprs) => write!(f, "({}) == ({})", exprs.0, exprs.1),
+            &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Ne), ref exprs) => write!(f, "({}) != ({})", exprs.0, exprs.1),
+            &instr::ExprKind::BinOp(ast::OpKind::LogicOp(ast::LogicOpKind::And), ref exprs) => write!(f, "({}) && ({})", exprs.0, exprs.1),
+            &instr::ExprKind::BinOp(ast::OpKind::LogicOp(ast::LogicOpKind::Or), ref exprs) => write!(f, "({}) || ({})", exprs.0, exprs.1),
+            &instr::ExprKind::UnaryOp(ast::UnaryOpKind::Neg, ref expr) => write!(f, "-({})", expr),
+            &instr::ExprKind::UnaryOp(ast::UnaryOpKind::Not, ref expr) => write!(f, "!({})", expr),
+            &instr::ExprKind::Splat(ty, ref expr) => write!(f, "{}({})", ty, expr),
         }
     }
 }
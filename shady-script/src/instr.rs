@@ -1,21 +1,23 @@
 use ast;
 use std::collections::BTreeSet;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Block {
     pub ret: Option<Type>,
     pub instrs: Vec<Instr>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Item {
     pub ret: Type,
     pub kind: ast::ItemKind,
+    pub params: Vec<(String, Type)>,
     pub instrs: Vec<Instr>,
-    pub vars: BTreeSet<ast::KeyVar>
+    pub vars: BTreeSet<ast::KeyVar>,
+    pub builtins: BTreeSet<String>
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Instr {
     Decl(String, Type, Option<ExprKind>),
     Assignment(String, Expr),
@@ -23,22 +25,28 @@ pub enum Instr {
     ITE(ExprKind, Block, Option<Block>),
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Expr {
     pub ty: Type,
     pub expr: ExprKind
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ExprKind {
     KeyVar(ast::KeyVar),
-    Literal(String),
+    Literal(f64),
+    Colour(Vec<u8>),
     Bool(bool),
     Var(String),
     Application(String, Vec<ExprKind>),
+    Call(String, Vec<ExprKind>),
     Vec2(Box<(ExprKind, ExprKind)>),
     Vec3(Box<(ExprKind, ExprKind, ExprKind)>),
+    Vec4(Box<(ExprKind, ExprKind, ExprKind, ExprKind)>),
     BinOp(ast::OpKind, Box<(ExprKind, ExprKind)>),
+    UnaryOp(ast::UnaryOpKind, Box<ExprKind>),
+    Splat(Type, Box<ExprKind>),
+    Swizzle(Box<ExprKind>, Vec<usize>),
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -47,5 +55,7 @@ pub enum Type {
     Float,
     Bool,
     Vec2,
-    Vec3
+    Vec3,
+    Vec4,
+    Error
 }
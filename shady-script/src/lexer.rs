@@ -0,0 +1,248 @@
+use std::str::CharIndices;
+use std::iter::Peekable;
+
+use span::Span;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Tok<'input> {
+    Image,
+    Fn,
+    Return,
+    If,
+    Else,
+    True,
+    False,
+    Float,
+    Bool,
+    Vec2,
+    Vec3,
+    Vec4,
+    Name(&'input str),
+    Number(&'input str),
+    Colour(&'input str),
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semi,
+    Colon,
+    Dot,
+    Arrow,
+    Assign,
+    Or,
+    And,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Not,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum LexError {
+    UnexpectedChar(Span, char),
+    UnterminatedComment(Span),
+    InvalidColour(Span),
+}
+
+pub type Spanned<'input> = Result<(usize, Tok<'input>, usize), LexError>;
+
+pub struct Lexer<'input> {
+    input: &'input str,
+    chars: Peekable<CharIndices<'input>>,
+}
+
+impl<'input> Lexer<'input> {
+    pub fn new(input: &'input str) -> Lexer<'input> {
+        Lexer {
+            input: input,
+            chars: input.char_indices().peekable()
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    /// The offset of the next character, or the end of the input
+    fn offset(&mut self) -> usize {
+        let len = self.input.len();
+        self.chars.peek().map_or(len, |&(idx, _)| idx)
+    }
+
+    /// Consumes the next character if it is `c`
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, f: F) {
+        while self.peek().is_some_and(&f) {
+            self.chars.next();
+        }
+    }
+
+    /// Skips a block comment whose opening `/*` begins at `begin`, including
+    /// any comments nested inside it
+    fn block_comment(&mut self, begin: usize) -> Result<(), LexError> {
+        let mut depth = 1;
+
+        while depth > 0 {
+            match self.chars.next() {
+                Some((_, '/')) => if self.eat('*') {
+                    depth += 1;
+                },
+
+                Some((_, '*')) => if self.eat('/') {
+                    depth -= 1;
+                },
+
+                Some(_) => (),
+                None => return Err(LexError::UnterminatedComment(Span { begin: begin, end: begin + 2 }))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lexes a number whose first character, a digit or a point, has already
+    /// been consumed
+    fn number(&mut self, begin: usize) -> Tok<'input> {
+        self.take_while(|c| c.is_ascii_digit());
+
+        // Only take the point if a fraction follows, so that `1.x` is a swizzle
+        let mut rest = self.input[self.offset()..].chars();
+        let fraction = rest.next() == Some('.') && rest.next().is_some_and(|c| c.is_ascii_digit());
+
+        if fraction && !self.input[begin..].starts_with('.') {
+            self.chars.next();
+            self.take_while(|c| c.is_ascii_digit());
+        }
+
+        // Likewise an exponent needs its digits, optionally signed
+        let exponent = {
+            let rest = &self.input.as_bytes()[self.offset()..];
+            let sign = matches!(rest.get(1), Some(&b'+') | Some(&b'-')) as usize;
+
+            match rest.first() {
+                Some(&b'e') | Some(&b'E') if rest.get(1 + sign).is_some_and(u8::is_ascii_digit) => 1 + sign,
+                _ => 0
+            }
+        };
+
+        for _ in 0..exponent {
+            self.chars.next();
+        }
+
+        self.take_while(|c| c.is_ascii_digit());
+        Tok::Number(&self.input[begin..self.offset()])
+    }
+
+    /// Lexes a `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` colour whose `#` has
+    /// already been consumed
+    fn colour(&mut self, begin: usize) -> Result<Tok<'input>, LexError> {
+        self.take_while(|c| c.is_ascii_alphanumeric());
+        let digits = &self.input[begin + 1..self.offset()];
+
+        if [3, 4, 6, 8].contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            Ok(Tok::Colour(digits))
+        } else {
+            Err(LexError::InvalidColour(Span { begin: begin, end: self.offset() }))
+        }
+    }
+
+    fn word(&mut self, begin: usize) -> Tok<'input> {
+        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        match &self.input[begin..self.offset()] {
+            "image" => Tok::Image,
+            "fn" => Tok::Fn,
+            "return" => Tok::Return,
+            "if" => Tok::If,
+            "else" => Tok::Else,
+            "true" => Tok::True,
+            "false" => Tok::False,
+            "float" => Tok::Float,
+            "bool" => Tok::Bool,
+            "vec2" => Tok::Vec2,
+            "vec3" => Tok::Vec3,
+            "vec4" => Tok::Vec4,
+            // Key variables are plain names here, as a variable may shadow them
+            name => Tok::Name(name)
+        }
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Spanned<'input>;
+
+    fn next(&mut self) -> Option<Spanned<'input>> {
+        loop {
+            let (begin, c) = self.chars.next()?;
+
+            let tok = match c {
+                c if c.is_whitespace() => continue,
+
+                '/' if self.eat('/') => {
+                    self.take_while(|c| c != '\n');
+                    continue
+                },
+
+                '/' if self.eat('*') => match self.block_comment(begin) {
+                    Ok(()) => continue,
+                    Err(err) => return Some(Err(err))
+                },
+
+                c if c.is_ascii_digit() => self.number(begin),
+                '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => self.number(begin),
+                c if c.is_ascii_alphabetic() => self.word(begin),
+
+                '#' => match self.colour(begin) {
+                    Ok(tok) => tok,
+                    Err(err) => return Some(Err(err))
+                },
+
+                '(' => Tok::LParen,
+                ')' => Tok::RParen,
+                '{' => Tok::LBrace,
+                '}' => Tok::RBrace,
+                ',' => Tok::Comma,
+                ';' => Tok::Semi,
+                ':' => Tok::Colon,
+                '.' => Tok::Dot,
+                '+' => Tok::Plus,
+                '*' => Tok::Star,
+                '/' => Tok::Slash,
+                '%' => Tok::Percent,
+                '^' => Tok::Caret,
+                '-' => if self.eat('>') { Tok::Arrow } else { Tok::Minus },
+                '=' => if self.eat('=') { Tok::Eq } else { Tok::Assign },
+                '!' => if self.eat('=') { Tok::Ne } else { Tok::Not },
+                '<' => if self.eat('=') { Tok::Le } else { Tok::Lt },
+                '>' => if self.eat('=') { Tok::Ge } else { Tok::Gt },
+                '|' if self.eat('|') => Tok::Or,
+                '&' if self.eat('&') => Tok::And,
+
+                c => {
+                    let span = Span { begin: begin, end: begin + c.len_utf8() };
+                    return Some(Err(LexError::UnexpectedChar(span, c)))
+                }
+            };
+
+            return Some(Ok((begin, tok, self.offset())))
+        }
+    }
+}
@@ -1,23 +1,123 @@
 extern crate lalrpop_util;
 
+use std::fmt::{self, Write};
+use std::error::Error;
+
 pub use image::Image;
 
 pub mod ast;
 pub mod span;
+pub mod diagnostic;
 
 pub use analyse::AnalyseError;
 pub use image::Uniform;
+pub use eval::Inputs;
+pub use instr::Type;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub struct ParseError<'a>(lalrpop_util::ParseError<usize, (usize, &'a str), ()>);
+pub struct ParseError {
+    pub span: span::Span,
+    pub line: usize,
+    pub col: usize,
+    pub token: Option<String>,
+    pub expected: Vec<String>,
+}
+
+impl ParseError {
+    fn new(err: lalrpop_util::ParseError<usize, lexer::Tok, lexer::LexError>, input: &str) -> ParseError {
+        let (span, token, expected) = match err {
+            lalrpop_util::ParseError::InvalidToken { location } => {
+                let c = input[location..].chars().next();
+                let end = location + c.map(char::len_utf8).unwrap_or(0);
+                (span::Span { begin: location, end: end }, c.map(|c| c.to_string()), Vec::new())
+            },
+
+            lalrpop_util::ParseError::UnrecognizedToken { token: Some((begin, _, end)), expected } =>
+                (span::Span { begin: begin, end: end }, Some(input[begin..end].to_owned()), expected),
+
+            lalrpop_util::ParseError::UnrecognizedToken { token: None, expected } =>
+                (span::Span { begin: input.len(), end: input.len() }, None, expected),
+
+            lalrpop_util::ParseError::ExtraToken { token: (begin, _, end) } =>
+                (span::Span { begin: begin, end: end }, Some(input[begin..end].to_owned()), Vec::new()),
+
+            lalrpop_util::ParseError::User { error: lexer::LexError::UnexpectedChar(span, c) } =>
+                (span, Some(c.to_string()), Vec::new()),
+
+            // Point at the comment that was left open rather than the end of the script
+            lalrpop_util::ParseError::User { error: lexer::LexError::UnterminatedComment(span) } =>
+                (span, None, vec![r#""*/""#.to_owned()]),
+
+            lalrpop_util::ParseError::User { error: lexer::LexError::InvalidColour(span) } =>
+                (span, Some(input[span.begin..span.end].to_owned()), vec![r#""colour""#.to_owned()]),
+        };
+
+        let (line, col) = diagnostic::line_col(input, span.begin);
+
+        ParseError {
+            span: span,
+            line: line,
+            col: col,
+            token: token,
+            expected: expected.iter().map(|token| describe_token(token)).collect()
+        }
+    }
+
+    pub fn diagnostic(&self) -> diagnostic::Diagnostic {
+        diagnostic::Diagnostic::new(self.span, self.message(false))
+    }
+
+    fn message(&self, location: bool) -> String {
+        let mut message = match self.token {
+            Some(ref token) => format!("unexpected `{}`", token),
+            None => "unexpected end of script".to_owned()
+        };
+
+        if location {
+            write!(message, " at {}:{}", self.line, self.col).unwrap();
+        }
+
+        match self.expected.len() {
+            0 => (),
+            1 => write!(message, ", expected {}", self.expected[0]).unwrap(),
+            _ => write!(message, ", expected one of {}", self.expected.join(", ")).unwrap()
+        }
+
+        message
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.message(true))
+    }
+}
+
+impl Error for ParseError {}
+
+/// Turns a terminal name from the grammar into something fit for an error message
+fn describe_token(token: &str) -> String {
+    match token {
+        r#""number""# => "number".to_owned(),
+        r#""name""# => "name".to_owned(),
+        r#""colour""# => "hex colour".to_owned(),
+        _ => format!("`{}`", token.trim_matches('"'))
+    }
+}
 
 mod analyse;
+mod eval;
 mod instr;
 mod grammar;
+mod lexer;
 mod image;
+mod colour;
+mod domain;
+mod noise;
+mod sdf;
 pub mod functions;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub struct Shady {
     items: Vec<instr::Item>
 }
@@ -37,6 +137,19 @@ impl Shady {
         self.items.push(item)
     }
 
+    fn function(&self, name: &str) -> Option<&instr::Item> {
+        self.items.iter().find(|item| match item.kind {
+            ast::ItemKind::Function(ref sig) => sig.name == name,
+            _ => false
+        })
+    }
+
+    fn with_functions<F: FnMut(&instr::Item)>(&self, mut f: F) {
+        for item in self.items.iter().filter(|item| item.kind != ast::ItemKind::Image) {
+            f(item)
+        }
+    }
+
     pub fn with_images<F: FnMut(Image)>(&self, mut f: F) {
         for img in self.items.iter()
             .enumerate()
@@ -48,14 +161,682 @@ impl Shady {
     }
 }
 
-pub fn parse_input(input: &str) -> Result<ast::AST, ParseError>{
-    grammar::parse_AST(input).map_err(ParseError)
+pub fn parse_input(input: &str) -> Result<ast::AST, ParseError> {
+    grammar::parse_AST(lexer::Lexer::new(input)).map_err(|err| ParseError::new(err, input))
 }
 
 #[test]
 fn test() {
-    let ast = parse_file("../script.shy");
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut buffer = String::new();
+    File::open("../script.shy").unwrap().read_to_string(&mut buffer).unwrap();
+
+    let ast = parse_input(&buffer);
     println!("{:?}", ast);
-    let sdy = ast.analyse();
+    let sdy = ast.unwrap().analyse();
     println!("{:?}", sdy);
 }
+
+#[test]
+fn test_functions() {
+    let sdy = parse_input(r#"
+        fn wave(a: float, b: float) -> float {
+            sin(a * t) + b
+        }
+
+        image {
+            (wave(x, y), 0.5, 0.5)
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("float fn_wave(float a, float b, float t) {"));
+        assert!(shader.contains("fn_wave(x, y, t)"));
+    });
+}
+
+#[test]
+fn test_evaluate() {
+    let sdy = parse_input(r#"
+        fn half(a: float) -> float {
+            a / 2
+        }
+
+        image {
+            c = (x, y, half(t));
+            if x < 0.5 {
+                return (0, 0, 0);
+            };
+            c
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert_eq!(image.evaluate(&Inputs { x: 0.25, y: 0.5, t: 1.0, ..Default::default() }), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.evaluate(&Inputs { x: 0.75, y: 0.5, t: 1.0, ..Default::default() }), [0.75, 0.5, 0.5, 1.0]);
+    });
+}
+
+#[test]
+fn test_multiple_errors() {
+    let errs = parse_input(r#"
+        image {
+            a = b + 1;
+            c = a * (1, 2);
+            if (1, 2) {
+                d = sin(1, 2);
+            };
+            (1, true, b)
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    assert_eq!(errs.len(), 4);
+    match (&errs[0], &errs[1], &errs[2], &errs[3]) {
+        (&AnalyseError::UndefinedName(..),
+         &AnalyseError::ExpectedBoolean(..),
+         &AnalyseError::InvalidApplication(..),
+         &AnalyseError::IncorrectTupleTypes(..)) => (),
+        _ => panic!("Unexpected errors: {:?}", errs)
+    }
+
+    // Tuples of matching non-floats would otherwise reach the evaluator
+    for source in &["image { (true, true, true) }", "image { ((1, 2), (3, 4), (5, 6)) }"] {
+        let errs = parse_input(source).unwrap().analyse().unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].to_string(), "tuple elements must all be floats");
+    }
+}
+
+#[test]
+fn test_scoping() {
+    let sdy = parse_input(r#"
+        image {
+            a = 0;
+            if x < 0.5 {
+                a = 1;
+                b = (1, 1, 1);
+            } else {
+                b = 2;
+            };
+            (a, a, a)
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("float a = 0.0;"));
+        assert!(shader.contains("    a = 1.0;"));
+        assert!(shader.contains("vec3 b = vec3(1.0, 1.0, 1.0);"));
+        assert!(shader.contains("float b = 2.0;"));
+    });
+
+    let errs = parse_input(r#"
+        image {
+            if x < 0.5 {
+                a = 1;
+            };
+            (a, a, a)
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    match &errs[..] {
+        &[AnalyseError::UndefinedName(_, ref name)] => assert_eq!(name, "a"),
+        _ => panic!("Unexpected errors: {:?}", errs)
+    }
+}
+
+#[test]
+fn test_if_expression() {
+    let sdy = parse_input(r#"
+        image {
+            c = if x < 0.5 { 1 } else { a = y; a * 2 };
+            (c, c, if y > 0.5 { 1 } else { 0 })
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("float _tmp1;"));
+        assert!(shader.contains("float c = _tmp1;"));
+        assert!(shader.contains("return vec3(c, c, _tmp2);"));
+
+        assert_eq!(image.evaluate(&Inputs { x: 0.25, y: 0.25, ..Default::default() }), [1.0, 1.0, 0.0, 1.0]);
+        assert_eq!(image.evaluate(&Inputs { x: 0.75, y: 0.75, ..Default::default() }), [1.5, 1.5, 1.0, 1.0]);
+    });
+
+    let errs = parse_input(r#"
+        image {
+            c = if x < 0.5 { 1 };
+            d = if x < 0.5 { 1 } else { (1, 1) };
+            (c, d, 1)
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    match &errs[..] {
+        &[AnalyseError::ExpectedElse(_), AnalyseError::IncorrectBranchTypes(_, instr::Type::Float, instr::Type::Vec2)] => (),
+        _ => panic!("Unexpected errors: {:?}", errs)
+    }
+
+    let errs = parse_input(r#"
+        image {
+            c = if x < 0.5 { return (1, 0, 0); } else { return (0, 1, 0); };
+            c
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    match &errs[..] {
+        &[AnalyseError::NeverProducesValue(_)] => (),
+        _ => panic!("Unexpected errors: {:?}", errs)
+    }
+}
+
+#[test]
+fn test_return_paths() {
+    let errs = parse_input(r#"
+        fn f(a: float) -> float {
+            if a < 0.5 {
+                return 1;
+            };
+        }
+
+        fn g(a: float) -> float {
+            if a < 0.5 {
+                return 1;
+            } else {
+                return (1, 1);
+            };
+        }
+
+        image {
+            c = if x < 0.5 { return (0, 0, 0); } else { f(y) };
+            c
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    match &errs[..] {
+        &[AnalyseError::ExpectedReturn(_),
+          AnalyseError::IncorrectReturnType(_, instr::Type::Float, instr::Type::Vec2),
+          AnalyseError::IncorrectReturnType(_, instr::Type::Vec3, instr::Type::Float)] => (),
+        _ => panic!("Unexpected errors: {:?}", errs)
+    }
+}
+
+#[test]
+fn test_operators() {
+    let sdy = parse_input(r#"
+        image {
+            a = 3 - (1, 2);
+            b = (2, 1) == a;
+            (x - (y - 1), 1, if b { 1 } else { 0 })
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("vec2 a = (3.0) - (vec2(1.0, 2.0));"));
+        assert_eq!(image.evaluate(&Inputs { x: 0.5, y: 0.25, ..Default::default() }), [1.25, 1.0, 1.0, 1.0]);
+    });
+
+    let sdy = parse_input(r#"
+        image {
+            a = -x * 2 + 1;
+            b = x <= 0.5 && !(y >= 0.5) || x != y;
+            (a, -1, if b { 1 } else { 0 })
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("float a = ((-(x)) * (2.0)) + (1.0);"));
+        assert_eq!(image.evaluate(&Inputs { x: 0.25, y: 0.75, ..Default::default() }), [0.5, -1.0, 1.0, 1.0]);
+        assert_eq!(image.evaluate(&Inputs { x: 0.5, y: 0.5, ..Default::default() }), [0.0, -1.0, 0.0, 1.0]);
+        assert_eq!(image.evaluate(&Inputs { x: 0.75, y: 0.75, ..Default::default() }), [-0.5, -1.0, 0.0, 1.0]);
+    });
+
+    let sdy = parse_input(r#"
+        image {
+            a = (x, y, -1.5) % 1;
+            a ^ 2 * 2
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("vec3 a = mod(vec3(x, y, -(1.5)), vec3(1.0));"));
+        assert!(image.standalone_shader().contains("return (pow(a, vec3(2.0))) * (2.0);"));
+        assert_eq!(image.evaluate(&Inputs { x: 1.5, y: 0.25, ..Default::default() }), [0.5, 0.125, 0.5, 1.0]);
+    });
+
+    let errs = parse_input(r#"
+        image {
+            a = true + 1;
+            b = (1, 2) < (3, 4);
+            c = (1, 2) * (1, 2, 3);
+            d = !1 || -true;
+            (1, 1, 1)
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    match &errs[..] {
+        &[AnalyseError::IncorrectBinOpTypes(_, instr::Type::Bool, instr::Type::Float),
+          AnalyseError::IncorrectBinOpTypes(_, instr::Type::Vec2, instr::Type::Vec2),
+          AnalyseError::IncorrectBinOpTypes(_, instr::Type::Vec2, instr::Type::Vec3),
+          AnalyseError::IncorrectUnaryOpType(_, instr::Type::Float),
+          AnalyseError::IncorrectUnaryOpType(_, instr::Type::Bool)] => (),
+        _ => panic!("Unexpected errors: {:?}", errs)
+    }
+}
+
+#[test]
+fn test_diagnostics() {
+    let source = "image {\n    if (1, 2) {\n        return (0, 0, 0);\n    };\n    (1, 1, 1)\n}\n";
+    let errs = parse_input(source).unwrap().analyse().unwrap_err();
+
+    assert_eq!(errs[0].diagnostic().render("script.shy", source), "\
+error: expected bool in if condition, found vec2
+ --> script.shy:2:8
+  |
+2 |     if (1, 2) {
+  |        ^^^^^^");
+
+    let source = "image {\n    (1, 1, 1\n}\n";
+    let err = parse_input(source).unwrap_err();
+
+    assert_eq!(err.diagnostic().render("script.shy", source), "\
+error: unexpected `}`, expected one of `)`, `,`, `||`
+ --> script.shy:3:1
+  |
+3 | }
+  | ^");
+
+    let err = parse_input("image {\n    a = ").unwrap_err();
+    assert_eq!((err.line, err.col, err.token.clone()), (2, 9, None));
+    assert!(err.expected.contains(&"`(`".to_owned()));
+    assert!(err.expected.contains(&"number".to_owned()));
+    assert!(err.to_string().starts_with("unexpected end of script at 2:9, expected one of `"));
+}
+
+#[test]
+fn test_alpha() {
+    let sdy = parse_input(r#"
+        image {
+            if x < 0.5 {
+                return (x, y, 0, 0.5);
+            };
+
+            (1, 1, 1, 1) * y
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("vec4 image(float x, float y) {"));
+        assert!(image.standalone_shader().contains("colour = image(uv.x, uv.y);"));
+        assert_eq!(image.evaluate(&Inputs { x: 0.25, y: 0.5, ..Default::default() }), [0.25, 0.5, 0.0, 0.5]);
+        assert_eq!(image.evaluate(&Inputs { x: 0.75, y: 0.5, ..Default::default() }), [0.5, 0.5, 0.5, 0.5]);
+    });
+
+    let errs = parse_input(r#"
+        image {
+            if x < 0.5 {
+                return (1, 1, 1);
+            };
+
+            (1, 1, 1, 1)
+        }
+
+        image {
+            (x, y)
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    match &errs[..] {
+        &[AnalyseError::IncorrectReturnType(_, instr::Type::Vec3, instr::Type::Vec4),
+          AnalyseError::InvalidImageType(_, instr::Type::Vec2)] => (),
+        _ => panic!("Unexpected errors: {:?}", errs)
+    }
+}
+
+#[test]
+fn test_swizzle() {
+    let sdy = parse_input(r#"
+        image {
+            c = (x, y, 0.5);
+            d = c.zyx;
+            e = c.rg;
+            (e.x, e.g, d.b + (1, 2).y)
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("vec3 d = (c).zyx;"));
+        assert!(image.standalone_shader().contains("vec2 e = (c).xy;"));
+        assert!(image.standalone_shader().contains("return vec3((e).x, (e).y, ((d).z) + ((vec2(1.0, 2.0)).y));"));
+        assert_eq!(image.evaluate(&Inputs { x: 0.25, y: 0.75, ..Default::default() }), [0.25, 0.75, 2.25, 1.0]);
+    });
+
+    let errs = parse_input(r#"
+        image {
+            a = (1, 2).z;
+            b = (1, 2, 3).xg;
+            c = x.x;
+            (1, 1, 1)
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    match &errs[..] {
+        &[AnalyseError::InvalidSwizzle(_, instr::Type::Vec2, ref a),
+          AnalyseError::InvalidSwizzle(_, instr::Type::Vec3, ref b),
+          AnalyseError::InvalidSwizzle(_, instr::Type::Float, ref c)] if a == "z" && b == "xg" && c == "x" => (),
+        _ => panic!("Unexpected errors: {:?}", errs)
+    }
+}
+
+#[test]
+fn test_generic_builtins() {
+    let sdy = parse_input(r#"
+        image {
+            a = sin((x, y, t));
+            max(a, 0.5)
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("vec3 a = sin(vec3(x, y, t));"));
+        assert_eq!(image.evaluate(&Inputs { x: 0.0, y: 1.0, ..Default::default() }), [0.5, 1f32.sin(), 0.5, 1.0]);
+    });
+
+    let errs = parse_input(r#"
+        image {
+            a = min((1, 2), (1, 2, 3));
+            (1, 1, 1)
+        }
+    "#).unwrap().analyse().unwrap_err();
+
+    assert_eq!(errs[0].to_string(), "no function `min` taking (vec2, vec3); \
+        candidates are `min(genType, genType) -> genType`, `min(genType, float) -> genType`");
+}
+
+#[test]
+fn test_math_builtins() {
+    let sdy = parse_input(r#"
+        image {
+            p = (x, y);
+            a = clamp(mix(p, (1, 0), 0.5), 0, 0.6);
+            b = length((3, 4)) + distance(p, p) + dot(p, (2, 2));
+            c = cross((1, 0, 0), (0, 1, 0));
+            (step(0.5, a.x) + smoothstep(0, 1, 0.5), b, sign(-c.z) + fract(1.25) + atan(0, 1))
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("vec2 a = clamp(mix(p, vec2(1.0, 0.0), 0.5), 0.0, 0.6);"));
+        assert_eq!(image.evaluate(&Inputs { x: 0.5, y: 0.25, ..Default::default() }), [1.5, 6.5, -0.75, 1.0]);
+    });
+}
+
+#[test]
+fn test_noise() {
+    let sdy = parse_input(r#"
+        fn clouds(p: vec2) -> float {
+            fbm(p, 4)
+        }
+
+        image {
+            n = noise2((x, y) * 4) + noise3((x, y, t));
+            (clouds((x, y)), voronoi((x, y) * 8), n * hash((y, x)))
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+
+        // Shared helpers are defined once, before everything that uses them
+        assert_eq!(shader.matches("float hash(vec2 p) {").count(), 1);
+        assert!(shader.find("float hash(vec2 p) {") < shader.find("float noise2(vec2 p) {"));
+        assert!(shader.find("float fbm(vec2 p, float octaves) {") < shader.find("float fn_clouds(vec2 p) {"));
+        assert!(shader.contains("float _hash3(vec3 p3) {"));
+        assert!(shader.contains("float voronoi(vec2 p) {"));
+
+        let colour = image.evaluate(&Inputs { x: 0.3, y: 0.7, t: 0.5, ..Default::default() });
+        assert!(colour.iter().all(|c| c.is_finite() && *c >= 0.0 && *c <= 2.0));
+    });
+
+    // Value noise passes through the hash at lattice points
+    assert_eq!(noise::noise2([3.0, -5.0]), noise::hash([3.0, -5.0]));
+    assert!((0..100).map(|i| noise::hash([i as f32, 0.5])).all(|h| (0.0..1.0).contains(&h)));
+}
+
+#[test]
+fn test_sdf() {
+    let sdy = parse_input(r#"
+        image {
+            p = (x, y) - 0.5;
+            d = op_smooth_union(sd_circle(p, 0.25), sd_box(p - (0.25, 0), (0.1, 0.2)), 0.05);
+            d = op_subtract(d, sd_ngon(p, 0.1, 6));
+            fill(d, (1, 0.5, 0)) * stroke(sd_segment(p, (0, 0), (1, 1)), 0.2)
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("vec4 image(float x, float y) {"));
+        assert!(shader.contains("float sd_ngon(vec2 p, float r, float n) {"));
+        assert!(shader.contains("float aa = fwidth(d);"));
+
+        assert_eq!(image.evaluate(&Inputs { x: 0.7, y: 0.7, ..Default::default() }), [1.0, 0.5, 0.0, 1.0]);
+        assert_eq!(image.evaluate(&Inputs { x: 0.5, y: 0.5, ..Default::default() })[3], 0.0);
+        assert_eq!(image.evaluate(&Inputs { x: 0.6, y: 0.3, ..Default::default() })[3], 0.0);
+    });
+
+    // Edges are antialiased over a pixel once the resolution is known
+    let sdy = parse_input("image { fill(sd_circle((x, y) - 0.5, 0.25), (1, 1, 1)) }").unwrap().analyse().unwrap();
+    sdy.with_images(|image| {
+        let inputs = Inputs { x: 0.75, y: 0.5, w: 100.0, h: 100.0, ..Default::default() };
+        assert_eq!(image.evaluate(&inputs)[3], 0.5);
+
+        let inputs = Inputs { x: 0.745, ..inputs };
+        assert!(image.evaluate(&inputs)[3] > 0.5 && image.evaluate(&inputs)[3] < 1.0);
+    });
+
+    assert_eq!(sdf::fill(0.0, [1.0, 1.0, 1.0], 0.01)[3], 0.5);
+    assert_eq!(sdf::stroke(0.1, 0.2, 0.01), 0.5);
+    assert!((sdf::sd_box([1.0, 0.0], [0.5, 0.5]) - 0.5).abs() < 1e-6);
+    assert!((sdf::sd_ngon([0.0, -2.0], 1.0, 4.0) - (2.0 - 0.5f32.sqrt())).abs() < 1e-5);
+    assert!((sdf::sd_triangle([0.25, 0.25], [0.0, 0.0], [1.0, 0.0], [0.0, 1.0]) + 0.25).abs() < 1e-6);
+    assert!((sdf::sd_triangle([0.0, -1.0], [0.0, 0.0], [1.0, 0.0], [0.0, 1.0]) - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_colour() {
+    use std::f32::consts::PI;
+
+    let sdy = parse_input(r#"
+        image {
+            a = hsv(x, 1, 1) + oklch(0.7, 0.1, y);
+            palette(t, a, (0.5, 0.5, 0.5), (1, 1, 1), (0, 0.33, 0.67))
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.find("vec3 linear_to_srgb(vec3 c) {") < shader.find("vec3 oklab(float l, float a, float b) {"));
+        assert!(shader.contains("vec3 palette(float t, vec3 a, vec3 b, vec3 c, vec3 d) {"));
+        assert_eq!(shader.matches("#define _PI").count(), 1);
+    });
+
+    let close = |a: [f32; 3], b: [f32; 3]| a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-4);
+
+    assert!(close(colour::hsv(1.0 / 3.0, 1.0, 0.5), [0.0, 0.5, 0.0]));
+    assert!(close(colour::hsl(2.0 / 3.0, 1.0, 0.75), [0.5, 0.5, 1.0]));
+    assert!(close(colour::rgb_to_hsv([0.2, 0.4, 0.8]), [0.6111111, 0.75, 0.8]));
+    assert!(close(colour::rgb_to_hsl(colour::hsl(0.1, 0.6, 0.3)), [0.1, 0.6, 0.3]));
+    assert!(close(colour::linear_to_srgb(colour::srgb_to_linear([0.01, 0.5, 1.0])), [0.01, 0.5, 1.0]));
+    assert!(close(colour::rgb_to_oklab([1.0, 1.0, 1.0]), [1.0, 0.0, 0.0]));
+    assert!(close(colour::oklch(0.6, 0.1, 0.2), colour::oklab(0.6, 0.1 * (0.4 * PI).cos(), 0.1 * (0.4 * PI).sin())));
+    assert!(close(colour::rgb_to_oklch(colour::oklch(0.6, 0.1, 0.2)), [0.6, 0.1, 0.2]));
+}
+
+#[test]
+fn test_resolution_vars() {
+    let sdy = parse_input(r#"
+        image {
+            p = ((x - 0.5) * aspect, y - 0.5);
+            (p.x, px / w + py / h, frame * dt)
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("uniform float width;\nuniform float height;\nuniform int frame;\nuniform float delta_time;\n"));
+        assert!(shader.contains("vec3 image(float x, float y, float w, float h, float aspect, float px, float py, float frame, float dt) {"));
+        assert!(shader.contains("image(uv.x, uv.y, width, height, width / height, uv.x * width, uv.y * height, float(frame), delta_time)"));
+        assert!(image.standalone_uniforms() == vec![Uniform::Width, Uniform::Height, Uniform::Frame, Uniform::DeltaTime]);
+
+        let inputs = Inputs { x: 0.75, y: 0.5, w: 200.0, h: 100.0, frame: 30, dt: 0.5, ..Default::default() };
+        assert_eq!(image.evaluate(&inputs), [0.5, 1.25, 15.0, 1.0]);
+    });
+
+    let sdy = parse_input("image { (aspect, px, py) }").unwrap().analyse().unwrap();
+    sdy.with_images(|image| assert_eq!(image.evaluate(&Default::default()), [1.0, 0.0, 0.0, 1.0]));
+}
+
+#[test]
+fn test_polar() {
+    let sdy = parse_input(r#"
+        image {
+            p = kaleido(rotate((x, y) - 0.5, t), 6);
+            q = repeat(mirror(p), 0.25);
+            (r, theta, q.x + q.y)
+        }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("vec3 image(float x, float y, float t, float r, float theta) {"));
+        assert!(shader.contains(", length(vec2((uv.x - 0.5) * width / height, uv.y - 0.5)), atan(uv.y - 0.5, (uv.x - 0.5) * width / height))"));
+        assert!(shader.contains("vec2 repeat(vec2 p, float period) {"));
+
+        let colour = image.evaluate(&Inputs { x: 0.75, y: 1.0, w: 200.0, h: 100.0, ..Default::default() });
+        assert!((colour[0] - 0.5f32.sqrt()).abs() < 1e-6);
+        assert!((colour[1] - std::f32::consts::FRAC_PI_4).abs() < 1e-6);
+        assert!(image.evaluate(&Default::default()).iter().all(|c| c.is_finite()));
+    });
+
+    let close = |a: [f32; 2], b: [f32; 2]| (a[0] - b[0]).abs() < 1e-6 && (a[1] - b[1]).abs() < 1e-6;
+
+    assert!(close(domain::rotate([1.0, 0.0], std::f32::consts::FRAC_PI_2), [0.0, 1.0]));
+    assert!(close(domain::kaleido([0.0, 1.0], 4.0), domain::kaleido([1.0, 0.0], 4.0)));
+    assert!(close(domain::kaleido([-1.0, -0.2], 2.0), [1.0, 0.2]));
+    assert_eq!(domain::repeat(0.3, 0.25), 0.3 - 0.25);
+}
+
+#[test]
+fn test_comments() {
+    let source = "// A gradient\nimage {\n    /* outer /* nested */ still a comment */\n    a = x; // trailing\n    (a, y, /* inline */ 1)\n}\n";
+    let sdy = parse_input(source).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("return vec3(a, y, 1.0);"));
+    });
+
+    // Spans still index into the original source
+    let source = "/* header */\nimage {\n    // b is never defined\n    a = b;\n    (1, 1, 1)\n}\n";
+    let errs = parse_input(source).unwrap().analyse().unwrap_err();
+    assert_eq!(&source[errs[0].span().begin..errs[0].span().end], "b");
+
+    let source = "image {\n    /* /* */\n    (1, 1, 1)\n}\n";
+    let err = parse_input(source).unwrap_err();
+
+    assert_eq!(err.diagnostic().render("script.shy", source), "\
+error: unexpected end of script, expected `*/`
+ --> script.shy:2:5
+  |
+2 |     /* /* */
+  |     ^^");
+
+    let err = parse_input("image { a = 1 @ 2; (1, 1, 1) }").unwrap_err();
+    assert_eq!(err.to_string(), "unexpected `@` at 1:15");
+}
+
+#[test]
+fn test_literals() {
+    let source = "image {\n    my_var = .5 + 1e-3 + 2.5E+1;\n    t = t * 2;\n    r = #ff8000;\n    r * my_var * t\n}\n";
+    let sdy = parse_input(source).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("float my_var = ((0.5) + (0.001)) + (25.0);"));
+        assert!(shader.contains("float _t = (t) * (2.0);"));
+        assert!(shader.contains("vec3 _r = vec3(1.0, 0.5019608, 0.0);"));
+        assert!(shader.contains("vec3 image(float x, float y, float t)"));
+
+        let inputs = Inputs { t: 1.0, ..Default::default() };
+        let expected = 2.0 * (0.5 + 1e-3 + 25.0);
+        assert!((image.evaluate(&inputs)[0] - expected).abs() < 1e-4);
+    });
+
+    // Spans cover the whole of the literal
+    let err = parse_input("image { (1, 1, 1) * #ff800 }").unwrap_err();
+    assert_eq!(err.to_string(), "unexpected `#ff800` at 1:21, expected hex colour");
+
+    // Names GLSL reserves can't be declared, as strict drivers reject them
+    let errs = parse_input(r#"
+        fn a__f(in: float) -> float { in }
+        image { gl_Pos = 1; a__b = 2; mat3 = 3; (1, 1, 1) }
+    "#).unwrap().analyse().unwrap_err();
+
+    let names = errs.iter().map(|err| match *err {
+        AnalyseError::ReservedName(_, ref name) => name.as_str(),
+        _ => panic!("Unexpected error: {:?}", err)
+    }).collect::<Vec<_>>();
+
+    assert_eq!(names, ["a__f", "in", "gl_Pos", "a__b", "mat3"]);
+    assert_eq!(errs[1].to_string(), "`in` is reserved by GLSL");
+
+    // Variables live apart from functions in scripts, but not in GLSL
+    let sdy = parse_input(r#"
+        fn wave(a: float) -> float { a }
+        image { mix = 1; fn_wave = 2; (mix(x, y, 0.5), mix, wave(fn_wave)) }
+    "#).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("float _mix = 1.0;"));
+        assert!(shader.contains("float _fn_wave = 2.0;"));
+        assert!(shader.contains("return vec3(mix(x, y, 0.5), _mix, fn_wave(_fn_wave));"));
+        assert_eq!(image.evaluate(&Inputs { y: 1.0, ..Default::default() }), [0.5, 1.0, 2.0, 1.0]);
+    });
+}
+
+#[test]
+fn test_colour_literals() {
+    let source = "image {\n    a = #f80 + cornflowerblue;\n    red = #ff000080;\n    (a.r, a.g, a.b, red.a)\n}\n";
+    let sdy = parse_input(source).unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        let shader = image.standalone_shader();
+        assert!(shader.contains("vec3 a = (vec3(1.0, 0.53333336, 0.0)) + (vec3(0.39215687, 0.58431375, 0.92941177));"));
+        assert!(shader.contains("vec4 red = vec4(1.0, 0.0, 0.0, 0.5019608);"));
+
+        let pixel = image.evaluate(&Default::default());
+        assert!((pixel[1] - (136.0 + 149.0) / 255.0).abs() < 1e-6);
+        assert!((pixel[3] - 128.0 / 255.0).abs() < 1e-6);
+    });
+
+    let errs = parse_input("image { cornflower }").unwrap().analyse().unwrap_err();
+    assert_eq!(errs[0].to_string(), "undefined name `cornflower`");
+}
+
+#[test]
+fn test_literal_normalisation() {
+    let sdy = parse_input("image { (1 / 2, 1e-7, 16777217) }").unwrap().analyse().unwrap();
+
+    sdy.with_images(|image| {
+        assert!(image.standalone_shader().contains("return vec3((1.0) / (2.0), 1.0e-7, 16777217.0);"));
+    });
+
+    let errs = parse_input("image { (1e39, 1e-50, 0e-50) }").unwrap().analyse().unwrap_err();
+    assert_eq!(errs.len(), 2);
+    assert_eq!(errs[0].to_string(), "number `1e39` is out of range for a float");
+    assert_eq!(errs[1].to_string(), "number `1e-50` is out of range for a float");
+}
@@ -0,0 +1,172 @@
+//! Procedural noise builtins. Each has a GLSL prelude that is prepended to
+//! the shader when used, and a CPU implementation written to match it
+//! operation for operation so that headless renders agree with the GPU.
+
+pub const HASH: &'static str = r#"float hash(vec2 p) {
+    vec3 p3 = fract(vec3(p.xyx) * 0.1031);
+    p3 += dot(p3, p3.yzx + 33.33);
+    return fract((p3.x + p3.y) * p3.z);
+}"#;
+
+pub const HASH3: &'static str = r#"float _hash3(vec3 p3) {
+    p3 = fract(p3 * 0.1031);
+    p3 += dot(p3, p3.zyx + 31.32);
+    return fract((p3.x + p3.y) * p3.z);
+}"#;
+
+pub const NOISE2: &'static str = r#"float noise2(vec2 p) {
+    vec2 i = floor(p);
+    vec2 f = fract(p);
+    vec2 u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(hash(i), hash(i + vec2(1.0, 0.0)), u.x),
+        mix(hash(i + vec2(0.0, 1.0)), hash(i + vec2(1.0, 1.0)), u.x),
+        u.y);
+}"#;
+
+pub const NOISE3: &'static str = r#"float noise3(vec3 p) {
+    vec3 i = floor(p);
+    vec3 f = fract(p);
+    vec3 u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(
+            mix(_hash3(i), _hash3(i + vec3(1.0, 0.0, 0.0)), u.x),
+            mix(_hash3(i + vec3(0.0, 1.0, 0.0)), _hash3(i + vec3(1.0, 1.0, 0.0)), u.x),
+            u.y),
+        mix(
+            mix(_hash3(i + vec3(0.0, 0.0, 1.0)), _hash3(i + vec3(1.0, 0.0, 1.0)), u.x),
+            mix(_hash3(i + vec3(0.0, 1.0, 1.0)), _hash3(i + vec3(1.0, 1.0, 1.0)), u.x),
+            u.y),
+        u.z);
+}"#;
+
+pub const FBM: &'static str = r#"float fbm(vec2 p, float octaves) {
+    float value = 0.0;
+    float amplitude = 0.5;
+    for (int i = 0; i < 16; i++) {
+        if (float(i) >= octaves) {
+            break;
+        }
+        value += amplitude * noise2(p);
+        p *= 2.0;
+        amplitude *= 0.5;
+    }
+    return value;
+}"#;
+
+pub const VORONOI: &'static str = r#"float voronoi(vec2 p) {
+    vec2 i = floor(p);
+    vec2 f = fract(p);
+    float d = 8.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec2 cell = vec2(float(x), float(y));
+            vec2 point = vec2(hash(i + cell), hash(i + cell + vec2(57.0, 113.0)));
+            d = min(d, length(cell + point - f));
+        }
+    }
+    return d;
+}"#;
+
+/// The number of octaves `fbm` is capped at, matching the GLSL loop bound
+const MAX_OCTAVES: usize = 16;
+
+fn fract(a: f32) -> f32 {
+    a - a.floor()
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+fn fade(f: f32) -> f32 {
+    f * f * (3.0 - 2.0 * f)
+}
+
+pub fn hash(p: [f32; 2]) -> f32 {
+    let mut p3 = [fract(p[0] * 0.1031), fract(p[1] * 0.1031), fract(p[0] * 0.1031)];
+    let d = p3[0] * (p3[1] + 33.33) + p3[1] * (p3[2] + 33.33) + p3[2] * (p3[0] + 33.33);
+
+    for c in &mut p3 {
+        *c += d;
+    }
+
+    fract((p3[0] + p3[1]) * p3[2])
+}
+
+fn hash3(p: [f32; 3]) -> f32 {
+    let mut p3 = [fract(p[0] * 0.1031), fract(p[1] * 0.1031), fract(p[2] * 0.1031)];
+    let d = p3[0] * (p3[2] + 31.32) + p3[1] * (p3[1] + 31.32) + p3[2] * (p3[0] + 31.32);
+
+    for c in &mut p3 {
+        *c += d;
+    }
+
+    fract((p3[0] + p3[1]) * p3[2])
+}
+
+pub fn noise2(p: [f32; 2]) -> f32 {
+    let (i, f) = ([p[0].floor(), p[1].floor()], [fract(p[0]), fract(p[1])]);
+    let u = [fade(f[0]), fade(f[1])];
+    let corner = |x: f32, y: f32| hash([i[0] + x, i[1] + y]);
+
+    mix(
+        mix(corner(0.0, 0.0), corner(1.0, 0.0), u[0]),
+        mix(corner(0.0, 1.0), corner(1.0, 1.0), u[0]),
+        u[1])
+}
+
+pub fn noise3(p: [f32; 3]) -> f32 {
+    let (i, f) = ([p[0].floor(), p[1].floor(), p[2].floor()], [fract(p[0]), fract(p[1]), fract(p[2])]);
+    let u = [fade(f[0]), fade(f[1]), fade(f[2])];
+    let corner = |x: f32, y: f32, z: f32| hash3([i[0] + x, i[1] + y, i[2] + z]);
+
+    mix(
+        mix(
+            mix(corner(0.0, 0.0, 0.0), corner(1.0, 0.0, 0.0), u[0]),
+            mix(corner(0.0, 1.0, 0.0), corner(1.0, 1.0, 0.0), u[0]),
+            u[1]),
+        mix(
+            mix(corner(0.0, 0.0, 1.0), corner(1.0, 0.0, 1.0), u[0]),
+            mix(corner(0.0, 1.0, 1.0), corner(1.0, 1.0, 1.0), u[0]),
+            u[1]),
+        u[2])
+}
+
+pub fn fbm(p: [f32; 2], octaves: f32) -> f32 {
+    let mut p = p;
+    let mut value = 0.0;
+    let mut amplitude = 0.5;
+
+    for i in 0..MAX_OCTAVES {
+        if i as f32 >= octaves {
+            break;
+        }
+
+        value += amplitude * noise2(p);
+        p = [p[0] * 2.0, p[1] * 2.0];
+        amplitude *= 0.5;
+    }
+
+    value
+}
+
+pub fn voronoi(p: [f32; 2]) -> f32 {
+    let (i, f) = ([p[0].floor(), p[1].floor()], [fract(p[0]), fract(p[1])]);
+    let mut d: f32 = 8.0;
+
+    for y in -1..2 {
+        for x in -1..2 {
+            let cell = [x as f32, y as f32];
+            let point = [
+                hash([i[0] + cell[0], i[1] + cell[1]]),
+                hash([i[0] + cell[0] + 57.0, i[1] + cell[1] + 113.0])
+            ];
+
+            let (dx, dy) = (cell[0] + point[0] - f[0], cell[1] + point[1] - f[1]);
+            d = d.min((dx * dx + dy * dy).sqrt());
+        }
+    }
+
+    d
+}
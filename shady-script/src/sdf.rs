@@ -0,0 +1,168 @@
+//! Signed distance functions for 2D shapes, the operations that combine
+//! them, and the antialiased `fill` and `stroke` that turn them into colour.
+//! Distances are negative inside a shape.
+
+use std::f32::consts::PI;
+
+pub const SD_CIRCLE: &'static str = r#"float sd_circle(vec2 p, float r) {
+    return length(p) - r;
+}"#;
+
+pub const SD_BOX: &'static str = r#"float sd_box(vec2 p, vec2 b) {
+    vec2 d = abs(p) - b;
+    return length(max(d, 0.0)) + min(max(d.x, d.y), 0.0);
+}"#;
+
+pub const SD_SEGMENT: &'static str = r#"float sd_segment(vec2 p, vec2 a, vec2 b) {
+    vec2 pa = p - a;
+    vec2 ba = b - a;
+    float h = clamp(dot(pa, ba) / dot(ba, ba), 0.0, 1.0);
+    return length(pa - ba * h);
+}"#;
+
+pub const SD_TRIANGLE: &'static str = r#"float sd_triangle(vec2 p, vec2 p0, vec2 p1, vec2 p2) {
+    vec2 e0 = p1 - p0;
+    vec2 e1 = p2 - p1;
+    vec2 e2 = p0 - p2;
+    vec2 v0 = p - p0;
+    vec2 v1 = p - p1;
+    vec2 v2 = p - p2;
+    vec2 pq0 = v0 - e0 * clamp(dot(v0, e0) / dot(e0, e0), 0.0, 1.0);
+    vec2 pq1 = v1 - e1 * clamp(dot(v1, e1) / dot(e1, e1), 0.0, 1.0);
+    vec2 pq2 = v2 - e2 * clamp(dot(v2, e2) / dot(e2, e2), 0.0, 1.0);
+    float s = sign(e0.x * e2.y - e0.y * e2.x);
+    vec2 d = min(min(
+        vec2(dot(pq0, pq0), s * (v0.x * e0.y - v0.y * e0.x)),
+        vec2(dot(pq1, pq1), s * (v1.x * e1.y - v1.y * e1.x))),
+        vec2(dot(pq2, pq2), s * (v2.x * e2.y - v2.y * e2.x)));
+    return -sqrt(d.x) * sign(d.y);
+}"#;
+
+pub const SD_NGON: &'static str = r#"float sd_ngon(vec2 p, float r, float n) {
+    float an = _PI / n;
+    float a = mod(atan(p.y, p.x) + an, 2.0 * an) - an;
+    vec2 q = length(p) * vec2(cos(a), abs(sin(a)));
+    vec2 d = q - vec2(r * cos(an), clamp(q.y, 0.0, r * sin(an)));
+    return length(d) * sign(d.x);
+}"#;
+
+pub const OP_UNION: &'static str = r#"float op_union(float a, float b) {
+    return min(a, b);
+}"#;
+
+pub const OP_SMOOTH_UNION: &'static str = r#"float op_smooth_union(float a, float b, float k) {
+    float h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}"#;
+
+pub const OP_SUBTRACT: &'static str = r#"float op_subtract(float a, float b) {
+    return max(a, -b);
+}"#;
+
+pub const OP_INTERSECT: &'static str = r#"float op_intersect(float a, float b) {
+    return max(a, b);
+}"#;
+
+pub const FILL: &'static str = r#"vec4 fill(float d, vec3 colour) {
+    float aa = fwidth(d);
+    return vec4(colour, 1.0 - smoothstep(-aa, aa, d));
+}"#;
+
+pub const STROKE: &'static str = r#"float stroke(float d, float width) {
+    float aa = fwidth(d);
+    return 1.0 - smoothstep(width * 0.5 - aa, width * 0.5 + aa, abs(d));
+}"#;
+
+fn clamp(a: f32, lo: f32, hi: f32) -> f32 {
+    a.max(lo).min(hi)
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+fn sign(a: f32) -> f32 {
+    if a == 0.0 { 0.0 } else { a.signum() }
+}
+
+fn sub(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn length(a: [f32; 2]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+pub fn sd_circle(p: [f32; 2], r: f32) -> f32 {
+    length(p) - r
+}
+
+pub fn sd_box(p: [f32; 2], b: [f32; 2]) -> f32 {
+    let d = [p[0].abs() - b[0], p[1].abs() - b[1]];
+    length([d[0].max(0.0), d[1].max(0.0)]) + d[0].max(d[1]).min(0.0)
+}
+
+pub fn sd_segment(p: [f32; 2], a: [f32; 2], b: [f32; 2]) -> f32 {
+    let (pa, ba) = (sub(p, a), sub(b, a));
+    let h = clamp(dot(pa, ba) / dot(ba, ba), 0.0, 1.0);
+    length([pa[0] - ba[0] * h, pa[1] - ba[1] * h])
+}
+
+pub fn sd_triangle(p: [f32; 2], p0: [f32; 2], p1: [f32; 2], p2: [f32; 2]) -> f32 {
+    let s = {
+        let (e0, e2) = (sub(p1, p0), sub(p0, p2));
+        sign(e0[0] * e2[1] - e0[1] * e2[0])
+    };
+
+    // The squared distance to each edge, and which side of it the point is on
+    let edge = |a: [f32; 2], b: [f32; 2]| {
+        let (e, v) = (sub(b, a), sub(p, a));
+        let h = clamp(dot(v, e) / dot(e, e), 0.0, 1.0);
+        let pq = [v[0] - e[0] * h, v[1] - e[1] * h];
+        (dot(pq, pq), s * (v[0] * e[1] - v[1] * e[0]))
+    };
+
+    let (d0, s0) = edge(p0, p1);
+    let (d1, s1) = edge(p1, p2);
+    let (d2, s2) = edge(p2, p0);
+
+    -d0.min(d1).min(d2).sqrt() * sign(s0.min(s1).min(s2))
+}
+
+pub fn sd_ngon(p: [f32; 2], r: f32, n: f32) -> f32 {
+    let an = PI / n;
+    let a = p[1].atan2(p[0]) + an;
+    let a = a - 2.0 * an * (a / (2.0 * an)).floor() - an;
+    let q = [length(p) * a.cos(), length(p) * a.sin().abs()];
+    let d = [q[0] - r * an.cos(), q[1] - clamp(q[1], 0.0, r * an.sin())];
+    length(d) * sign(d[0])
+}
+
+pub fn op_smooth_union(a: f32, b: f32, k: f32) -> f32 {
+    let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    mix(b, a, h) - k * h * (1.0 - h)
+}
+
+/// Like GLSL's, except that edges which coincide give a hard step
+fn smoothstep(e0: f32, e1: f32, x: f32) -> f32 {
+    if e0 == e1 {
+        return if x < e0 { 0.0 } else { 1.0 }
+    }
+
+    let t = clamp((x - e0) / (e1 - e0), 0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// The CPU evaluator has no screen-space derivatives, so `fill` and `stroke`
+/// take the pixel size in place of `fwidth(d)`, giving hard edges when it is 0
+pub fn fill(d: f32, colour: [f32; 3], aa: f32) -> [f32; 4] {
+    [colour[0], colour[1], colour[2], 1.0 - smoothstep(-aa, aa, d)]
+}
+
+pub fn stroke(d: f32, width: f32, aa: f32) -> f32 {
+    1.0 - smoothstep(width * 0.5 - aa, width * 0.5 + aa, d.abs())
+}
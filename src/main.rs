@@ -1,7 +1,7 @@
 #![feature(type_ascription, slice_patterns)]
 
 #[macro_use] extern crate glium;
-extern crate clap;
+#[macro_use] extern crate clap;
 extern crate notify;
 extern crate shady_script;
 extern crate imagefmt;
@@ -13,7 +13,7 @@ extern crate cocoa;
 #[macro_use] extern crate objc;
 
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::io::Read;
 use std::sync::mpsc::channel;
 use std::time::Instant;
@@ -25,13 +25,14 @@ use glium::glutin::{WindowId, EventsLoop, Event, WindowEvent};
 use glium::texture::RawImage2d;
 use glium::texture::texture2d::Texture2d;
 use glium::backend::glutin::Display;
-use glium::uniforms::EmptyUniforms;
+use glium::uniforms::{Uniforms, UniformValue};
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 use notify::{RecommendedWatcher, Watcher, RecursiveMode};
 
-use shady_script::{ParseError, AnalyseError, Uniform};
+use shady_script::{Shady, Uniform, Inputs};
+use shady_script::diagnostic::Diagnostic;
 
 mod platform;
 
@@ -78,16 +79,24 @@ struct ImageDisplay {
 }
 
 #[derive(Debug)]
-enum Error<'a> {
+enum Error {
     IO(std::io::Error),
-    Parse(ParseError<'a>),
-    Analyse(AnalyseError),
+    Script(Vec<Diagnostic>),
+    Image(imagefmt::Error),
 }
 
-fn load_images<'a, P: AsRef<Path>>(buffer: &'a mut String, event_loop: &EventsLoop, displays: &mut Vec<ImageDisplay>, path: P) -> Result<(), Error<'a>> {
-    buffer.clear();
+fn report(err: &Error, path: &Path, source: &str) {
+    match *err {
+        Error::IO(ref err) => println!("error: {}", err),
+        Error::Image(ref err) => println!("error: could not write image: {:?}", err),
+        Error::Script(ref diagnostics) => for diagnostic in diagnostics {
+            println!("{}\n", diagnostic.render(&path.to_string_lossy(), source));
+        },
+    }
+}
 
-    let mut idx = 0usize;
+fn load_script<P: AsRef<Path>>(buffer: &mut String, path: P) -> Result<Shady, Error> {
+    buffer.clear();
 
     if let Err(err) = File::open(path).and_then(|mut file| file.read_to_string(buffer)) {
         return Err(Error::IO(err))
@@ -95,13 +104,19 @@ fn load_images<'a, P: AsRef<Path>>(buffer: &'a mut String, event_loop: &EventsLo
 
     let ast = match shady_script::parse_input(buffer) {
         Ok(ast) => ast,
-        Err(err) => return Err(Error::Parse(err))
+        Err(err) => return Err(Error::Script(vec![err.diagnostic()]))
     };
 
-    let sdy = match ast.analyse() {
-        Ok(sdy) => sdy,
-        Err(err) => return Err(Error::Analyse(err))
-    };
+    match ast.analyse() {
+        Ok(sdy) => Ok(sdy),
+        Err(errs) => Err(Error::Script(errs.iter().map(|err| err.diagnostic()).collect()))
+    }
+}
+
+fn load_images<P: AsRef<Path>>(buffer: &mut String, event_loop: &EventsLoop, displays: &mut Vec<ImageDisplay>, path: P) -> Result<(), Error> {
+    let mut idx = 0usize;
+
+    let sdy = try!(load_script(buffer, path));
 
     sdy.with_images(|image| {
         let shader = image.standalone_shader();
@@ -144,6 +159,67 @@ fn load_images<'a, P: AsRef<Path>>(buffer: &'a mut String, event_loop: &EventsLo
     Ok(())
 }
 
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let mut parts = size.splitn(2, 'x');
+
+    match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+        (Some(Ok(w)), Some(Ok(h))) if w > 0 && h > 0 => Some((w, h)),
+        _ => None
+    }
+}
+
+fn render_images<P: AsRef<Path>>(buffer: &mut String, path: P, (w, h): (u32, u32), time: f32, out: &Path) -> Result<(), Error> {
+    let sdy = try!(load_script(buffer, path));
+
+    let mut images = Vec::new();
+    sdy.with_images(|image| {
+        let mut data = Vec::with_capacity(w as usize * h as usize * 4);
+
+        // PNG rows run top to bottom, whereas uv.y runs bottom to top
+        for row in (0..h).rev() {
+            for col in 0..w {
+                let colour = image.evaluate(&Inputs {
+                    x: (col as f32 + 0.5) / w as f32,
+                    y: (row as f32 + 0.5) / h as f32,
+                    t: time,
+                    w: w as f32,
+                    h: h as f32,
+                    ..Default::default()
+                });
+
+                for c in &colour {
+                    data.push((c.max(0.0).min(1.0) * 255.0).round() as u8);
+                }
+            }
+        }
+
+        images.push(data);
+    });
+
+    let count = images.len();
+    for (idx, data) in images.into_iter().enumerate() {
+        let path = if count == 1 {
+            out.to_owned()
+        } else {
+            let stem = out.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            out.with_file_name(format!("{}_{}.png", stem, idx))
+        };
+
+        let mut file = match File::create(&path) {
+            Ok(file) => file,
+            Err(err) => return Err(Error::IO(err))
+        };
+
+        if let Err(err) = png::write(&mut file, w as usize, h as usize, ColFmt::RGBA, &data, ColType::Auto, None) {
+            return Err(Error::Image(err))
+        }
+
+        println!("Rendered image {} to {}", idx, path.display());
+    }
+
+    Ok(())
+}
+
 fn with_display<F: FnMut(&mut ImageDisplay)>(displays: &mut [ImageDisplay], id: WindowId, mut f: F) {
     for display in displays {
         if display.id == id {
@@ -157,6 +233,7 @@ fn main() {
     let matches = App::new("Shady")
         .author("Samuel Sleight <samuel.sleight@gmail.com>")
         .version("0.1.0")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("script")
              .help("The script to load images from")
              .required(true))
@@ -168,8 +245,46 @@ fn main() {
              .help("Keep watching the script if all windows are closed")
              .long("keep")
              .short("k"))
+        .subcommand(SubCommand::with_name("render")
+             .about("Render each image in a script to a PNG file without opening a window")
+             .arg(Arg::with_name("script")
+                  .help("The script to load images from")
+                  .required(true))
+             .arg(Arg::with_name("size")
+                  .help("The size of the rendered images, as WIDTHxHEIGHT")
+                  .long("size")
+                  .short("s")
+                  .takes_value(true)
+                  .default_value("500x500")
+                  .validator(|size| parse_size(&size).map(|_| ()).ok_or("expected a size such as 512x512".to_owned())))
+             .arg(Arg::with_name("time")
+                  .help("The value of t to render the images at")
+                  .long("time")
+                  .short("t")
+                  .takes_value(true)
+                  .default_value("0"))
+             .arg(Arg::with_name("out")
+                  .help("The file to write to; an index is appended when the script has several images")
+                  .long("out")
+                  .short("o")
+                  .takes_value(true)))
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("render") {
+        let path = Path::new(matches.value_of("script").unwrap());
+        let size = parse_size(matches.value_of("size").unwrap()).unwrap();
+        let time = value_t!(matches, "time", f32).unwrap_or_else(|err| err.exit());
+        let out = matches.value_of("out").map(PathBuf::from).unwrap_or_else(|| path.with_extension("png"));
+
+        let mut buffer = String::new();
+        if let Err(err) = render_images(&mut buffer, path, size, time, &out) {
+            report(&err, path, &buffer);
+            std::process::exit(1);
+        }
+
+        return
+    }
+
     let path = Path::new(matches.value_of("script").unwrap());
     let once = matches.is_present("once");
     let keep = !once && matches.is_present("keep");
@@ -179,7 +294,7 @@ fn main() {
     let mut event_loop = EventsLoop::new();
 
     if let Err(err) = load_images(&mut buffer, &event_loop, &mut displays, path) {
-        println!("{:?}", err);
+        report(&err, path, &buffer);
     }
 
     let watcher = if once {
@@ -192,19 +307,26 @@ fn main() {
     };
 
     let mut time = Instant::now();
+    let mut last_frame = Instant::now();
+    let mut frame = 0u32;
     loop {
         if let Some((ref rx, _)) = watcher {
             if let Ok(_) = rx.try_recv() {
                 time = Instant::now();
+                frame = 0;
 
                 if let Err(err) = load_images(&mut buffer, &event_loop, &mut displays, path) {
-                    println!("{:?}", err);
+                    report(&err, path, &buffer);
                 }
             };
         };
 
         let duration = time.elapsed().subsec_nanos() as f32 / 1000000000.0;
 
+        let dt = last_frame.elapsed();
+        let dt = dt.as_secs() as f32 + dt.subsec_nanos() as f32 / 1000000000.0;
+        last_frame = Instant::now();
+
         event_loop.poll_events(|event| {
             if let Event::WindowEvent { event: e, window_id: id } = event {
                 match e {
@@ -222,15 +344,7 @@ fn main() {
                 platform::save_image(|path| {
                     let tex = Texture2d::empty(&display.display, size.0, size.1).unwrap();
                     let mut target = tex.as_surface();
-                    render(
-                        &mut target, 
-                        &display.program, 
-                        &display.buffer, 
-                        &display.uniforms, 
-                        duration, 
-                        display.mouse_position.0 as f32 / size.0 as f32, 
-                        display.mouse_position.1 as f32 / size.1 as f32
-                    );
+                    render(&mut target, &display.program, &display.buffer, &display.uniforms, inputs);
 
                     let raw: RawImage2d<u8> = tex.read();
                     let mut file = File::create(path).unwrap();
@@ -242,20 +356,25 @@ fn main() {
             let size = display.display.gl_window().window().get_inner_size_pixels().unwrap();
             let mut target = display.display.draw();
 
-            render(
-                &mut target, 
-                &display.program, 
-                &display.buffer, 
-                &display.uniforms, 
-                duration, 
-                display.mouse_position.0 as f32 / size.0 as f32, 
-                display.mouse_position.1 as f32 / size.1 as f32
-            );
+            let inputs = Inputs {
+                t: duration,
+                mx: display.mouse_position.0 as f32 / size.0 as f32,
+                my: display.mouse_position.1 as f32 / size.1 as f32,
+                w: size.0 as f32,
+                h: size.1 as f32,
+                frame: frame,
+                dt: dt,
+                ..Default::default()
+            };
+
+            render(&mut target, &display.program, &display.buffer, &display.uniforms, inputs);
 
             target.finish().unwrap();
 
         }
 
+        frame += 1;
+
         displays.retain(|display| !display.done);
         if displays.is_empty() && !keep {
             break
@@ -263,55 +382,41 @@ fn main() {
     }
 }
 
-fn render<S: Surface>(surface: &mut S, program: &Program, buffer: &VertexBuffer<Vertex>, uniforms: &[Uniform], time: f32, mx: f32, my: f32) {
-    surface.clear_color(0.0, 0.0, 0.0, 0.0);
-
-    macro_rules! render {
-        ($uniforms:expr) => (surface.draw(
-            buffer, 
-            &glium::index::NoIndices(glium::index::PrimitiveType::TriangleFan), 
-            program, 
-            &$uniforms, 
-            &Default::default()
-        ).unwrap())
-    };
-
-    match uniforms {
-        &[] => render!(EmptyUniforms),
-
-        &[Uniform::Time] => render!(uniform! {
-            time: time
-        }),
-
-        &[Uniform::MouseX] => render!(uniform! {
-            mouse_x: mx
-        }),
-
-        &[Uniform::MouseY] => render!(uniform! {
-            mouse_y: my
-        }),
-
-        &[Uniform::Time, Uniform::MouseX] => render!(uniform! {
-            time: time,
-            mouse_x: mx
-        }),
-
-        &[Uniform::Time, Uniform::MouseY] => render!(uniform! {
-            time: time,
-            mouse_y: my
-        }),
+/// The values of an image's uniforms for a single frame
+struct ImageUniforms<'a> {
+    uniforms: &'a [Uniform],
+    inputs: Inputs,
+}
 
-        &[Uniform::MouseX, Uniform::MouseY] => render!(uniform! {
-            mouse_x: mx,
-            mouse_y: my
-        }),
+impl<'b> Uniforms for ImageUniforms<'b> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        for uniform in self.uniforms {
+            match *uniform {
+                Uniform::Time => f("time", UniformValue::Float(self.inputs.t)),
+                Uniform::MouseX => f("mouse_x", UniformValue::Float(self.inputs.mx)),
+                Uniform::MouseY => f("mouse_y", UniformValue::Float(self.inputs.my)),
+                Uniform::Width => f("width", UniformValue::Float(self.inputs.w)),
+                Uniform::Height => f("height", UniformValue::Float(self.inputs.h)),
+                Uniform::Frame => f("frame", UniformValue::SignedInt(self.inputs.frame as i32)),
+                Uniform::DeltaTime => f("delta_time", UniformValue::Float(self.inputs.dt)),
+            }
+        }
+    }
+}
 
-        &[Uniform::Time, Uniform::MouseX, Uniform::MouseY] => render!(uniform! {
-            time: time,
-            mouse_x: mx,
-            mouse_y: my,
-        }),
+fn render<S: Surface>(surface: &mut S, program: &Program, buffer: &VertexBuffer<Vertex>, uniforms: &[Uniform], inputs: Inputs) {
+    surface.clear_color(0.0, 0.0, 0.0, 0.0);
 
-        _ => panic!("Unexpected uniform format - this shouldn't happen")
+    let uniforms = ImageUniforms {
+        uniforms: uniforms,
+        inputs: inputs
     };
+
+    surface.draw(
+        buffer, 
+        &glium::index::NoIndices(glium::index::PrimitiveType::TriangleFan), 
+        program, 
+        &uniforms, 
+        &Default::default()
+    ).unwrap();
 }
@@ -13,15 +13,14 @@ mod platform {
 
     pub fn open_window(event_loop: &EventsLoop, title: &str, (w, h): (u32, u32)) -> Display {
         Display::new(
-            glium::glutin::WindowBuilder::new()
+            WindowBuilder::new()
                 .with_title(title)
                 .with_dimensions(w, h),
             ContextBuilder::new(),
             event_loop
         )
-            .unwrap();
-
+            .unwrap()
     }
 
-    pub fn save_image<F: FnMut(Path)>(_: F) {}
+    pub fn save_image<F: FnMut(&Path)>(_: F) {}
 }